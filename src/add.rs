@@ -0,0 +1,214 @@
+use log::{info, warn};
+use std::env;
+use std::path::{self, Path, PathBuf};
+
+use crate::config::{
+    append_mapping, convert_line_to_mapping, get_config_path, load_config, Config, Mapping,
+};
+use crate::fs::{escape_pattern, get_pattern_root};
+use crate::git::GitError;
+use crate::limits::LimitViolation;
+use crate::metadata::record_metadata;
+use crate::process::{
+    destination_prefix, find_mapping_matches, get_found_mappings, index_found_mappings,
+    is_excluded, is_mapping_linked,
+};
+
+/// It adds files to the config file and the working directory. Files already matched by a mapping
+/// are only linked, otherwise a mapping line is suggested, which `edit_line` may change or reject,
+/// appended to the config file, and its files are linked and indexed right away the same way refresh
+/// does, and their metadata is recorded.
+///
+/// Arguments:
+///
+/// * `paths`: The paths of the files or directories to add.
+/// * `edit_line`: A function that confirms or changes the suggested mapping line.
+/// * `choose_excludes`: A function that picks the left out files to exclude from now on.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn add<F, G>(paths: &[String], mut edit_line: F, mut choose_excludes: G) -> Result<(), GitError>
+where
+    F: FnMut(&str) -> Option<String>,
+    G: FnMut(&[LimitViolation]) -> Vec<String>,
+{
+    let config_path = get_config_path();
+    let mut added = false;
+    for path in paths {
+        let path = path::absolute(path).unwrap_or_else(|_| panic!("Failed to resolve {path:?}"));
+        if path.symlink_metadata().is_err() {
+            warn!("Skipped {:?}, since it doesn't exist", &path);
+            continue;
+        }
+        let config =
+            load_config(&config_path).expect("Failed to load config file. Try initializing first.");
+        let mapping = match find_covering_mapping(&config, &path) {
+            Some(mapping) => {
+                info!("{:?} is already mapped by {}", &path, mapping);
+                mapping
+            }
+            None if path.to_string_lossy().contains(':') => {
+                warn!(
+                    "Skipped {:?}, since mapping lines are split on ':'. Add a mapping matching it with `?` in place of ':' instead.",
+                    &path
+                );
+                continue;
+            }
+            None => {
+                let Some(line) = edit_line(&suggest_mapping_line(&config, &path)) else {
+                    warn!("Skipped {:?}", &path);
+                    continue;
+                };
+                if line.split(':').count() != 2 {
+                    warn!("Skipped {:?}, since {line:?} isn't a mapping", &path);
+                    continue;
+                }
+                let mapping = convert_line_to_mapping(line);
+                append_mapping(&config_path, &mapping)
+                    .unwrap_or_else(|e| panic!("Failed to add mapping to config file: {:?}", e));
+                info!("Added `{mapping}` to the config file");
+                mapping
+            }
+        };
+        let config =
+            load_config(&config_path).expect("Failed to load config file. Try initializing first.");
+        let found = find_mapping_matches(&mapping)
+            .into_iter()
+            .filter(|m| Path::new(&m.source).starts_with(&path) && !is_excluded(&config, m))
+            .collect::<Vec<Mapping>>();
+        let found = index_found_mappings(&config, found, false, &mut choose_excludes)?;
+        info!(
+            "Linked and indexed {} file(s) from {:?}",
+            found.len(),
+            &path
+        );
+        added |= !found.is_empty();
+    }
+    if added {
+        let config =
+            load_config(&config_path).expect("Failed to load config file. Try initializing first.");
+        let linked = get_found_mappings(&config)
+            .into_iter()
+            .filter(is_mapping_linked)
+            .collect::<Vec<Mapping>>();
+        record_metadata(&config, &linked)?;
+    }
+    Ok(())
+}
+
+/// It finds the config mapping whose pattern already matches the path, or any file below it
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `path`: The absolute path of the file or directory.
+///
+/// Returns:
+///
+/// An Option<Mapping>
+fn find_covering_mapping(config: &Config, path: &Path) -> Option<Mapping> {
+    config
+        .mappings
+        .iter()
+        .find(|m| {
+            find_mapping_matches(m)
+                .iter()
+                .any(|f| Path::new(&f.source).starts_with(path))
+        })
+        .cloned()
+}
+
+/// It suggests a mapping line for a path. The path is placed below the destination of the nearest
+/// mapping whose pattern root contains it, or gets a destination computed from its location otherwise.
+/// Directories are mapped with all the files below them. The path is escaped, so that wildcards and
+/// capture groups in its name are taken literally.
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `path`: The absolute path of the file or directory.
+///
+/// Returns:
+///
+/// A String
+fn suggest_mapping_line(config: &Config, path: &Path) -> String {
+    let nearest = config
+        .mappings
+        .iter()
+        .filter(|m| m.destination.contains('('))
+        .map(|m| (get_pattern_root(&m.source), m))
+        .filter(|(root, _)| path.starts_with(root) && path != root)
+        .max_by_key(|(root, _)| root.components().count());
+    let destination = match nearest {
+        Some((root, mapping)) => {
+            let prefix = destination_prefix(mapping).trim_end_matches('/');
+            let rest = path.strip_prefix(&root).unwrap_or(path);
+            Path::new(prefix).join(rest).display().to_string()
+        }
+        None => literal_destination(path),
+    };
+    let source = escape_pattern(&path.display().to_string());
+    if path.is_dir() && !path.is_symlink() {
+        format!("{source}/(**/*) : {destination}/(1)")
+    } else {
+        format!("{source} : {destination}")
+    }
+}
+
+/// It computes a destination from the location of a path, relative to the home directory when it's
+/// inside it, with the leading dot of hidden names dropped, e.g. `~/.config/nvim` becomes
+/// `config/nvim`
+///
+/// Arguments:
+///
+/// * `path`: The absolute path.
+///
+/// Returns:
+///
+/// A String
+fn literal_destination(path: &Path) -> String {
+    let home = env::var("HOME").map(PathBuf::from).unwrap_or_default();
+    let relative = path
+        .strip_prefix(&home)
+        .ok()
+        .filter(|r| !r.as_os_str().is_empty())
+        .unwrap_or_else(|| path.strip_prefix("/").unwrap_or(path));
+    relative
+        .iter()
+        .map(|c| {
+            let c = c.to_string_lossy();
+            c.strip_prefix('.').unwrap_or(&c).to_string()
+        })
+        .collect::<PathBuf>()
+        .display()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn suggest_mapping_line_escapes_the_source() {
+        let dir = env::temp_dir().join(format!("configman-add-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("Failed to create the test directory");
+        let path = dir.join("a[1]*(x)?.conf");
+        for file in [&path, &dir.join("a1(x)b.conf")] {
+            fs::write(file, "").expect("Failed to create a test file");
+        }
+        let mapping = convert_line_to_mapping(suggest_mapping_line(&Config::default(), &path));
+        assert_eq!(
+            mapping.source,
+            format!("{}/a[[]1[]][*][(]x[)][?].conf", dir.display())
+        );
+        let sources = find_mapping_matches(&mapping)
+            .into_iter()
+            .map(|m| m.source)
+            .collect::<Vec<String>>();
+        assert_eq!(sources, vec![path.display().to_string()]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,183 @@
+use log::{info, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{Config, Mapping};
+use crate::fs::{move_dir, remove_from_fs};
+use crate::git::{rm_cached, GitError};
+use crate::process::{get_tracked_mappings, index_directories, resolve_destination};
+
+/// `AdoptOptions` is a struct that describes which directories to adopt or give back.
+///
+/// Properties:
+///
+/// * `undo`: Whether to move the directories back out of the working directory.
+/// * `paths`: The source directories to act on, all directory mappings if empty.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct AdoptOptions {
+    pub undo: bool,
+    pub paths: Vec<String>,
+}
+
+/// It checks whether a mapping is declared as a directory mapping with `@mode = directory`, which
+/// moves the whole directory into the working directory and leaves a symbolic link in its place
+///
+/// Arguments:
+///
+/// * `mapping`: The mapping.
+///
+/// Returns:
+///
+/// A boolean value.
+pub fn is_directory_mapping(mapping: &Mapping) -> bool {
+    mapping.settings_named("mode").last() == Some(&"directory")
+}
+
+/// It checks whether the source of a directory mapping is a symbolic link to its copy in the working
+/// directory
+///
+/// Arguments:
+///
+/// * `mapping`: The directory mapping.
+///
+/// Returns:
+///
+/// A boolean value.
+pub fn is_adopted(mapping: &Mapping) -> bool {
+    let source = Path::new(&mapping.source);
+    let destination = resolve_destination(&mapping.destination);
+    source.is_symlink()
+        && source
+            .canonicalize()
+            .is_ok_and(|s| destination.canonicalize().is_ok_and(|d| s == d))
+}
+
+/// It returns the copies of every directory mapping in the working directory, adopted or not
+///
+/// Arguments:
+///
+/// * `config`: &Config
+///
+/// Returns:
+///
+/// A vector of PathBufs
+pub fn get_directory_destinations(config: &Config) -> Vec<PathBuf> {
+    get_tracked_mappings(config)
+        .iter()
+        .filter(|m| is_directory_mapping(m))
+        .map(|m| resolve_destination(&m.destination))
+        .collect()
+}
+
+/// It creates a symbolic link in place of the source directory pointing to its copy in the working
+/// directory
+///
+/// Arguments:
+///
+/// * `copy`: The directory in the working directory.
+/// * `source`: The path of the link.
+pub fn link_directory(copy: &Path, source: &PathBuf) {
+    source.parent().map(fs::create_dir_all);
+    std::os::unix::fs::symlink(copy, source)
+        .unwrap_or_else(|_| panic!("Failed to link to {:?} as {:?}", &copy, &source))
+}
+
+/// It adopts the directory mappings, or moves them back out of the working directory if asked to
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `options`: The adopt options.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn adopt(config: &Config, options: &AdoptOptions) -> Result<(), GitError> {
+    let mappings = get_tracked_mappings(config)
+        .into_iter()
+        .filter(is_directory_mapping)
+        .filter(|m| {
+            options.paths.is_empty()
+                || options
+                    .paths
+                    .iter()
+                    .any(|p| Path::new(p) == Path::new(&m.source))
+        })
+        .collect::<Vec<Mapping>>();
+    if mappings.is_empty() {
+        warn!("No directory mappings found. Declare them with `@mode = directory`.");
+    }
+    for mapping in &mappings {
+        if options.undo {
+            undo_adoption(mapping)?;
+        } else {
+            adopt_directory(config, mapping)?;
+        }
+    }
+    Ok(())
+}
+
+/// It moves the source directory into the working directory, unless it's already there, links it
+/// back in its place and indexes the files below it
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `mapping`: The directory mapping.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+fn adopt_directory(config: &Config, mapping: &Mapping) -> Result<(), GitError> {
+    let source = PathBuf::from(&mapping.source);
+    let copy = resolve_destination(&mapping.destination);
+    if is_adopted(mapping) {
+        info!("{:?} is already adopted", &source);
+        return Ok(());
+    }
+    let real_dir = source.is_dir() && !source.is_symlink();
+    if real_dir && copy.exists() {
+        warn!(
+            "Failed to adopt {:?}, since {:?} exists too. Move one of them away first.",
+            &source, &copy
+        );
+        return Ok(());
+    } else if real_dir {
+        move_dir(&source, &copy);
+        info!("Moved {:?} to {:?}", &source, &copy);
+    } else if source.exists() || source.is_symlink() {
+        warn!("Failed to adopt {:?}, since it isn't a directory", &source);
+        return Ok(());
+    } else if !copy.is_dir() {
+        warn!("Failed to adopt {:?}, since it doesn't exist", &source);
+        return Ok(());
+    }
+    link_directory(&copy, &source);
+    index_directories(config, std::slice::from_ref(mapping), false)?;
+    info!("Linked {:?} to {:?}", &source, &copy);
+    Ok(())
+}
+
+/// It replaces the link at the source with the directory from the working directory, and unindexes
+/// the directory
+///
+/// Arguments:
+///
+/// * `mapping`: The directory mapping.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn undo_adoption(mapping: &Mapping) -> Result<(), GitError> {
+    let source = PathBuf::from(&mapping.source);
+    let copy = resolve_destination(&mapping.destination);
+    if !is_adopted(mapping) {
+        warn!("{:?} isn't adopted", &source);
+        return Ok(());
+    }
+    remove_from_fs(&source);
+    move_dir(&copy, &source);
+    rm_cached(&copy.display().to_string())?;
+    info!("Moved {:?} back to {:?}", &copy, &source);
+    Ok(())
+}
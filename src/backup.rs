@@ -0,0 +1,240 @@
+use log::info;
+use std::fs::{self, File};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tar::{Archive, Builder};
+
+use crate::config::{get_config_path, load_config};
+use crate::fs::remove_from_fs;
+use crate::git::get_git_path;
+
+/// The setting that limits how many backups are kept, `0` disabling them.
+pub const BACKUPS_KEY: &str = "backups";
+
+/// The number of backups kept unless `@backups` says otherwise.
+const DEFAULT_BACKUPS: usize = 10;
+
+/// The directory inside the git directory the backups are stored in, out of reach of cleaning and
+/// never committed.
+const BACKUP_DIR: &str = "configman/backups";
+
+/// `BackupAction` is an enum of the things that can be done with the backups.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackupAction {
+    List,
+    Restore(String),
+}
+
+/// `Backup` is a struct that describes a backup, a tarball named `<id>-<operation>.tar` whose entries
+/// are the absolute paths of the files without the leading `/`.
+///
+/// Properties:
+///
+/// * `id`: The time the backup was taken at, e.g. `20240101T120000.000Z`.
+/// * `operation`: The operation the backup was taken before, e.g. `clean`.
+/// * `path`: The path to the tarball.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Backup {
+    id: String,
+    operation: String,
+    path: PathBuf,
+}
+
+/// It returns how many backups are kept, as set with `@backups`
+///
+/// Returns:
+///
+/// A usize
+fn get_backup_limit() -> usize {
+    load_config(&get_config_path())
+        .ok()
+        .and_then(|c| c.setting(BACKUPS_KEY).map(String::from))
+        .map(|value| {
+            value
+                .parse()
+                .unwrap_or_else(|_| panic!("{:?} is not a valid value of @{BACKUPS_KEY}", value))
+        })
+        .unwrap_or(DEFAULT_BACKUPS)
+}
+
+/// It collects the files under a path whose contents would be lost if the path were removed, i.e.
+/// regular files that aren't hard linked anywhere else, like their source. Symbolic links are left
+/// out since their targets are untouched.
+///
+/// Arguments:
+///
+/// * `path`: The path to a file or directory.
+/// * `files`: The collected files.
+fn collect_unlinked_files(path: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(metadata) = path.symlink_metadata() else {
+        return;
+    };
+    if metadata.is_dir() {
+        for entry in path
+            .read_dir()
+            .unwrap_or_else(|_| panic!("Failed read from {:?}", &path))
+            .flatten()
+        {
+            collect_unlinked_files(&entry.path(), files);
+        }
+    } else if metadata.is_file() && metadata.nlink() == 1 {
+        files.push(path.to_path_buf());
+    }
+}
+
+/// It takes a backup of the files under the given paths before an operation destroys them, and drops
+/// the oldest backups over the limit. Nothing is backed up if every file is still linked elsewhere or
+/// backups are disabled.
+///
+/// Arguments:
+///
+/// * `operation`: The name of the operation, e.g. `clean`.
+/// * `paths`: The absolute paths about to be removed or replaced.
+///
+/// Returns:
+///
+/// The id of the backup, if one was taken
+pub fn back_up(operation: &str, paths: &[PathBuf]) -> Option<String> {
+    let limit = get_backup_limit();
+    let mut files = Vec::new();
+    for path in paths {
+        collect_unlinked_files(path, &mut files);
+    }
+    if limit == 0 || files.is_empty() {
+        return None;
+    }
+    let id = humantime::format_rfc3339_millis(SystemTime::now())
+        .to_string()
+        .replace(['-', ':'], "");
+    let dir = get_git_path(BACKUP_DIR);
+    fs::create_dir_all(&dir).unwrap_or_else(|_| panic!("Failed to create {:?}", &dir));
+    let path = dir.join(format!("{id}-{operation}.tar"));
+    let tarball = File::create(&path).unwrap_or_else(|_| panic!("Failed to create {:?}", &path));
+    let mut builder = Builder::new(tarball);
+    for file in &files {
+        builder
+            .append_path_with_name(file, file.strip_prefix("/").unwrap_or(file))
+            .unwrap_or_else(|e| panic!("Failed to back up {:?}: {e}", &file));
+    }
+    builder
+        .finish()
+        .unwrap_or_else(|e| panic!("Failed to write {:?}: {e}", &path));
+    info!(
+        "Backed up {} file(s) as {id} before {operation}",
+        files.len()
+    );
+    prune_backups(limit);
+    Some(id)
+}
+
+/// It lists the backups, oldest first
+///
+/// Returns:
+///
+/// A vector of Backup structs
+fn get_backups() -> Vec<Backup> {
+    let Ok(entries) = get_git_path(BACKUP_DIR).read_dir() else {
+        return Vec::new();
+    };
+    let mut backups = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let (id, operation) = name.strip_suffix(".tar")?.split_once('-')?;
+            Some(Backup {
+                id: id.to_string(),
+                operation: operation.to_string(),
+                path: entry.path(),
+            })
+        })
+        .collect::<Vec<Backup>>();
+    backups.sort_by(|a, b| a.id.cmp(&b.id));
+    backups
+}
+
+/// It removes the oldest backups until at most `limit` are left
+///
+/// Arguments:
+///
+/// * `limit`: The number of backups to keep.
+fn prune_backups(limit: usize) {
+    let backups = get_backups();
+    let excess = backups.len().saturating_sub(limit);
+    for backup in &backups[..excess] {
+        fs::remove_file(&backup.path)
+            .unwrap_or_else(|_| panic!("Failed to remove {:?}", &backup.path));
+    }
+}
+
+/// It opens the tarball of a backup
+///
+/// Arguments:
+///
+/// * `backup`: The backup.
+///
+/// Returns:
+///
+/// An Archive
+fn open_backup(backup: &Backup) -> Archive<File> {
+    let tarball =
+        File::open(&backup.path).unwrap_or_else(|_| panic!("Failed to open {:?}", &backup.path));
+    Archive::new(tarball)
+}
+
+/// It prints the backups along with the operation they were taken before and how many files they hold
+pub fn list_backups() {
+    let backups = get_backups();
+    if backups.is_empty() {
+        println!("There are no backups yet.");
+    }
+    for backup in &backups {
+        let count = open_backup(backup)
+            .entries()
+            .map(|entries| entries.count())
+            .unwrap_or(0);
+        println!("{}  {:<8} {} file(s)", backup.id, backup.operation, count);
+    }
+}
+
+/// It puts every file of a backup back where it was, replacing whatever is there now
+///
+/// Arguments:
+///
+/// * `id`: The id of the backup.
+pub fn restore_backup(id: &str) {
+    let backup = get_backups()
+        .into_iter()
+        .find(|b| b.id == id)
+        .unwrap_or_else(|| panic!("There is no backup {id}. Run `configman backups list`."));
+    let mut archive = open_backup(&backup);
+    let entries = archive
+        .entries()
+        .unwrap_or_else(|e| panic!("Failed to read {:?}: {e}", &backup.path));
+    for entry in entries {
+        let mut entry = entry.unwrap_or_else(|e| panic!("Failed to read {:?}: {e}", &backup.path));
+        let target = Path::new("/").join(
+            entry
+                .path()
+                .unwrap_or_else(|e| panic!("Failed to read {:?}: {e}", &backup.path)),
+        );
+        target.parent().map(fs::create_dir_all);
+        remove_from_fs(&target);
+        entry
+            .unpack(&target)
+            .unwrap_or_else(|e| panic!("Failed to restore {:?}: {e}", &target));
+        info!("Restored {:?}", &target);
+    }
+}
+
+/// It performs a backup action
+///
+/// Arguments:
+///
+/// * `action`: The backup action.
+pub fn backups(action: &BackupAction) {
+    match action {
+        BackupAction::List => list_backups(),
+        BackupAction::Restore(id) => restore_backup(id),
+    }
+}
@@ -2,28 +2,111 @@ use std::fmt;
 use std::fs::File;
 use std::io::{prelude::*, BufReader, Error};
 
-use indicatif::{ProgressBar, ProgressIterator};
+use crate::fs::get_working_dir;
+use crate::output::with_spinner;
+use crate::toml_config::{
+    append_toml_mapping, append_toml_setting, find_toml_mapping_line, load_toml_config,
+    remove_toml_mapping, write_toml_setting,
+};
 
-/// `Config` is a struct that contains a vector of `Mapping`s.
+/// The name of the line based config file.
+pub const CONFIG_FILE: &str = "config.cmf";
+
+/// The name of the structured config file, used instead of the line based one when it exists.
+pub const TOML_CONFIG_FILE: &str = "configman.toml";
+
+/// `Config` is a struct that contains a vector of `Mapping`s and the global settings.
 ///
 /// Properties:
 ///
 /// * `mappings`: A vector of Mapping structs.
-#[derive(Debug, Clone)]
+/// * `settings`: A vector of Setting structs, in the order they were declared.
+#[derive(Debug, Clone, Default)]
 pub struct Config {
     pub mappings: Vec<Mapping>,
+    pub settings: Vec<Setting>,
 }
 
-/// `Mapping` is a struct that contains two strings, `source` and `destination`.
+/// `Mapping` is a struct that contains two strings, `source` and `destination`, and the settings
+/// declared for it.
 ///
 /// Properties:
 ///
 /// * `source`: The source path of the file to be copied.
 /// * `destination`: The destination path of the file.
-#[derive(Debug, Clone)]
+/// * `settings`: The settings declared on indented lines following the mapping.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct Mapping {
     pub source: String,
     pub destination: String,
+    pub settings: Vec<Setting>,
+}
+
+/// `Setting` is a struct that contains a key and value declared as `@<key> = <value>`.
+///
+/// Properties:
+///
+/// * `key`: The name of the setting.
+/// * `value`: The value of the setting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Setting {
+    pub key: String,
+    pub value: String,
+}
+
+impl Mapping {
+    /// It returns the values of every declaration of a setting on this mapping, in order
+    ///
+    /// Arguments:
+    ///
+    /// * `key`: The name of the setting.
+    ///
+    /// Returns:
+    ///
+    /// A vector of &str
+    pub fn settings_named(&self, key: &str) -> Vec<&str> {
+        self.settings
+            .iter()
+            .filter(|s| s.key == key)
+            .map(|s| s.value.as_str())
+            .collect()
+    }
+}
+
+impl Config {
+    /// It returns the values of every global declaration of a setting, in order
+    ///
+    /// Arguments:
+    ///
+    /// * `key`: The name of the setting.
+    ///
+    /// Returns:
+    ///
+    /// A vector of &str
+    pub fn settings_named(&self, key: &str) -> Vec<&str> {
+        self.settings
+            .iter()
+            .filter(|s| s.key == key)
+            .map(|s| s.value.as_str())
+            .collect()
+    }
+
+    /// It returns the value of the last declaration of a setting
+    ///
+    /// Arguments:
+    ///
+    /// * `key`: The name of the setting.
+    ///
+    /// Returns:
+    ///
+    /// An Option<&str>
+    pub fn setting(&self, key: &str) -> Option<&str> {
+        self.settings
+            .iter()
+            .rev()
+            .find(|s| s.key == key)
+            .map(|s| s.value.as_str())
+    }
 }
 
 /// It's implementing the `Display` trait for the `Config` struct.
@@ -40,9 +123,49 @@ impl fmt::Display for Mapping {
     }
 }
 
+/// It returns the name of the config file of the working directory, `configman.toml` if there is one
+/// and `config.cmf` otherwise
+///
+/// Returns:
+///
+/// A &str
+pub fn get_config_file() -> &'static str {
+    if get_working_dir().join(TOML_CONFIG_FILE).exists() {
+        TOML_CONFIG_FILE
+    } else {
+        CONFIG_FILE
+    }
+}
+
+/// It returns the path to the config file of the working directory
+///
+/// Returns:
+///
+/// A String
+pub fn get_config_path() -> String {
+    get_working_dir()
+        .join(get_config_file())
+        .display()
+        .to_string()
+}
+
+/// It checks whether a config file is a structured one, by its extension
+///
+/// Arguments:
+///
+/// * `path`: &str - The path to the config file
+///
+/// Returns:
+///
+/// A boolean value.
+pub fn is_toml_config(path: &str) -> bool {
+    path.ends_with(".toml")
+}
+
 /// We open the file, wrap it in a buffered reader, get the lines, filter out the ones we don't care
-/// about, convert the ones we do care about to mappings, and then collect them into a vector inside
-/// a Config Struct
+/// about, convert the setting lines to settings and the other ones to mappings, and then collect them
+/// into a Config Struct. Indented setting lines belong to the mapping above them, the others are
+/// global. Structured config files are parsed as TOML instead.
 ///
 /// Arguments:
 ///
@@ -52,32 +175,226 @@ impl fmt::Display for Mapping {
 ///
 /// A Result<Config, Error>
 pub fn load_config(path: &str) -> Result<Config, Error> {
-    File::open(path)
-        .map(|f| BufReader::new(f))
-        .map(|br| br.lines())
-        .map(|ls| {
-            ls.progress_with(
-                ProgressBar::new_spinner()
-                    .with_message(format!("Loading config file: {:?}", &path)),
-            )
-            .map(|l| l.unwrap())
-            .filter(considered_mapping)
-            .map(convert_line_to_mapping)
+    if is_toml_config(path) {
+        return load_toml_config(path);
+    }
+    let lines = File::open(path).map(BufReader::new).map(|br| br.lines())?;
+    let mut config = Config::default();
+    for line in with_spinner(lines, format!("Loading config file: {:?}", &path))
+        .map(|l| l.unwrap())
+        .filter(|l| considered_mapping(l))
+    {
+        let indented = line.starts_with(char::is_whitespace);
+        match (convert_line_to_setting(&line), config.mappings.last_mut()) {
+            (Some(setting), Some(mapping)) if indented => mapping.settings.push(setting),
+            (Some(setting), _) => config.settings.push(setting),
+            (None, _) => config.mappings.push(convert_line_to_mapping(line)),
+        }
+    }
+    Ok(config)
+}
+
+/// It converts a line of the form `@<key> = <value>` to a Setting, if the line is a setting
+///
+/// Arguments:
+///
+/// * `l`: The line to convert
+///
+/// Returns:
+///
+/// An Option<Setting>
+pub fn convert_line_to_setting(l: &str) -> Option<Setting> {
+    l.trim()
+        .strip_prefix('@')
+        .map(|setting| match setting.split_once('=') {
+            Some((key, value)) => Setting {
+                key: key.trim().to_string(),
+                value: value.trim().to_string(),
+            },
+            None => panic!("Failed to parse line '{l}'.\nExpected format @<key> = <value>"),
         })
-        .map(|m| m.collect::<Vec<Mapping>>())
-        .map(|m| Config { mappings: m })
+}
+
+/// It sets a global setting in the config file, replacing its last declaration or appending a new one,
+/// and leaves every other line untouched
+///
+/// Arguments:
+///
+/// * `path`: &str - The path to the config file
+/// * `key`: The name of the setting.
+/// * `value`: The value of the setting.
+///
+/// Returns:
+///
+/// A Result<(), Error>
+pub fn write_setting(path: &str, key: &str, value: &str) -> Result<(), Error> {
+    if is_toml_config(path) {
+        return write_toml_setting(path, key, value);
+    }
+    let content = std::fs::read_to_string(path)?;
+    let mut lines = content.lines().map(String::from).collect::<Vec<String>>();
+    let declaration = format!("@{key} = {value}");
+    let existing = lines.iter().rposition(|l| {
+        !l.starts_with(char::is_whitespace)
+            && convert_line_to_setting(l).is_some_and(|s| s.key == key)
+    });
+    match existing {
+        Some(index) => lines[index] = declaration,
+        None => lines.push(declaration),
+    }
+    std::fs::write(path, lines.join("\n") + "\n")
+}
+
+/// It appends a global setting to the config file, keeping its earlier declarations
+///
+/// Arguments:
+///
+/// * `path`: &str - The path to the config file
+/// * `key`: The name of the setting.
+/// * `value`: The value of the setting.
+///
+/// Returns:
+///
+/// A Result<(), Error>
+pub fn append_setting(path: &str, key: &str, value: &str) -> Result<(), Error> {
+    if is_toml_config(path) {
+        return append_toml_setting(path, key, value);
+    }
+    append_line(path, &format!("@{key} = {value}"))
+}
+
+/// It appends a mapping to the config file, along with its settings
+///
+/// Arguments:
+///
+/// * `path`: &str - The path to the config file
+/// * `mapping`: The mapping to append.
+///
+/// Returns:
+///
+/// A Result<(), Error>
+pub fn append_mapping(path: &str, mapping: &Mapping) -> Result<(), Error> {
+    if is_toml_config(path) {
+        return append_toml_mapping(path, mapping);
+    }
+    append_line(
+        path,
+        &format!("{} : {}", mapping.source, mapping.destination),
+    )?;
+    for setting in &mapping.settings {
+        append_line(path, &format!("    @{} = {}", setting.key, setting.value))?;
+    }
+    Ok(())
+}
+
+/// It appends a line to the config file, leaving the existing lines untouched
+///
+/// Arguments:
+///
+/// * `path`: &str - The path to the config file
+/// * `line`: The line to append.
+///
+/// Returns:
+///
+/// A Result<(), Error>
+pub fn append_line(path: &str, line: &str) -> Result<(), Error> {
+    let content = std::fs::read_to_string(path)?;
+    let separator = if content.is_empty() || content.ends_with('\n') {
+        ""
+    } else {
+        "\n"
+    };
+    std::fs::write(path, format!("{content}{separator}{line}\n"))
+}
+
+/// It removes the line of a mapping from the config file, along with the settings declared on it, and
+/// leaves every other line untouched
+///
+/// Arguments:
+///
+/// * `path`: &str - The path to the config file
+/// * `mapping`: The mapping to remove.
+///
+/// Returns:
+///
+/// A Result of whether the mapping was found
+pub fn remove_mapping_line(path: &str, mapping: &Mapping) -> Result<bool, Error> {
+    if is_toml_config(path) {
+        return remove_toml_mapping(path, mapping);
+    }
+    let content = std::fs::read_to_string(path)?;
+    let mut kept = Vec::new();
+    let mut removing = false;
+    let mut found = false;
+    for line in content.lines() {
+        let indented = line.starts_with(char::is_whitespace);
+        if removing && indented && convert_line_to_setting(line).is_some() {
+            continue;
+        }
+        removing = !found && declares_mapping(line, mapping);
+        found |= removing;
+        if !removing {
+            kept.push(line);
+        }
+    }
+    if found {
+        std::fs::write(path, kept.join("\n") + "\n")?;
+    }
+    Ok(found)
+}
+
+/// It finds the number of the line declaring a mapping in the config file
+///
+/// Arguments:
+///
+/// * `path`: &str - The path to the config file
+/// * `mapping`: The config mapping.
+///
+/// Returns:
+///
+/// A Result of the line number, counting from 1, if the mapping was found
+pub fn find_mapping_line(path: &str, mapping: &Mapping) -> Result<Option<usize>, Error> {
+    if is_toml_config(path) {
+        return find_toml_mapping_line(path, mapping);
+    }
+    let content = std::fs::read_to_string(path)?;
+    Ok(content
+        .lines()
+        .position(|line| declares_mapping(line, mapping))
+        .map(|index| index + 1))
+}
+
+/// It checks whether a line of the config file declares a mapping
+///
+/// Arguments:
+///
+/// * `line`: The line of the config file.
+/// * `mapping`: The config mapping.
+///
+/// Returns:
+///
+/// A boolean value.
+fn declares_mapping(line: &str, mapping: &Mapping) -> bool {
+    !line.starts_with(char::is_whitespace)
+        && considered_mapping(line)
+        && convert_line_to_setting(line).is_none()
+        && line.split(':').count() == 2
+        && {
+            let declared = convert_line_to_mapping(line.to_string());
+            declared.source == mapping.source && declared.destination == mapping.destination
+        }
 }
 
 /// If the line is empty or starts with a hash, it's not a mapping
 ///
 /// Arguments:
 ///
-/// * `l`: &String - the line to be considered
+/// * `l`: &str - the line to be considered
 ///
 /// Returns:
 ///
 /// A boolean value.
-pub fn considered_mapping(l: &String) -> bool {
+pub fn considered_mapping(l: &str) -> bool {
     let tl = l.trim();
     !(tl.is_empty() || tl.starts_with('#'))
 }
@@ -99,6 +416,7 @@ pub fn convert_line_to_mapping(l: String) -> Mapping {
         2 => Mapping {
             source: splits[0].trim().to_string(),
             destination: splits[1].trim().to_string(),
+            settings: Vec::new(),
         },
         _ => panic!("Failed to parse line '{l}'.\nExpected format <source> : <destination>"),
     }
@@ -107,6 +425,50 @@ pub fn convert_line_to_mapping(l: String) -> Mapping {
 pub const SAMPLE_CONFIG_CONTENT: &str = "
 # This is a sample config file
 # The configuration file supports Unix shell style patterns when matching files
+# Run `configman config check` to find mistakes, and `configman config fmt` to tidy this file up
+# Run `configman config migrate` to convert this file to an equivalent configman.toml
+
+# Lines starting with @ are settings, e.g. the remote that is fetched from and pushed to
+# @remote = origin
+
+# Hooks are commands run around actions: pre-refresh, post-refresh, post-deploy, pre-commit and
+# pre-push. A failing pre-* hook aborts the action. Hooks on indented lines below a mapping only run
+# when files of that mapping are affected, which are listed in $CONFIGMAN_SOURCES and
+# $CONFIGMAN_DESTINATIONS.
+# @post-refresh = gitleaks protect --staged
+# /home/nomen/.config/polybar/(*) : polybar/(1)
+#     @post-deploy = polybar-msg cmd restart
+
+# Files that look like they contain secrets (private keys, tokens, high-entropy strings) are not
+# indexed. Known harmless files or values can be allowed, globally or on a mapping.
+# @allow-secret = /home/nomen/.config/nvim/**/*
+
+# Matched files can be excluded, and files over the size limits or binary files are left out unless
+# allowed. The limits can be overridden on a mapping, except for the total size.
+# @exclude = /home/nomen/.config/**/Cache/**/*
+# @max-file-size = 10M
+# @max-total-size = 100M
+# @allow-binary = false
+
+# The permissions of the files are recorded in metadata.cmf and restored on deploy. Owners are
+# recorded as well if asked to, globally or on a mapping.
+# @preserve-owner = false
+
+# Files that would be lost by cleaning, linking or a hard reset on fetch are backed up first. The
+# backups are listed with `configman backups list` and restored with `configman backups restore <id>`.
+# Only the latest ones are kept, and 0 turns backups off.
+# @backups = 10
+
+# Symbolic links to files among the matched files are hard-linked as they are by default. A mapping
+# can track the links themselves instead, recreating them with the same target on deploy, follow them
+# to the files they point to, or skip them.
+# /home/nomen/.config/(**/*) : config/(1)
+#     @symlinks = track
+
+# A directory mapping moves a whole directory into the working directory once it's adopted with
+# `configman adopt`, and leaves a symbolic link in its place. `configman adopt --undo` moves it back.
+# /home/nomen/.config/nvim : nvim
+#     @mode = directory
 
 # This mapping tracks the files in .mplayer directory and
 # maps them to mplayer directory the under current working directory
@@ -125,5 +487,6 @@ pub const SAMPLE_CONFIG_CONTENT: &str = "
 
 # /home/nomen/.config/neofetch/images/arch.png -> neofetch/images/arch.png
 /home/nomen/.config/neofetch/**/(*.png) : neofetch/(1) 
+    @allow-binary = true
 
 ";
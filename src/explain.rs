@@ -0,0 +1,435 @@
+use capturing_glob::Entry;
+use serde::Serialize;
+use std::fmt;
+use std::path::{self, Component, Path, PathBuf};
+
+use crate::adopt::{is_adopted, is_directory_mapping};
+use crate::config::{
+    find_mapping_line, get_config_file, get_config_path, load_config, Config, Mapping,
+};
+use crate::fs::{capture_path, get_matching_files, get_working_dir};
+use crate::git::{get_status, GitError, GitStatusMap};
+use crate::output::{print_json, OutputFormat};
+use crate::process::{
+    is_excluded, is_mapping_linked, resolve_destination, reverse_mapping, split_pattern_groups,
+    substitute_group_values,
+};
+
+/// `ExplainOptions` is a struct that describes which file to explain and how.
+///
+/// Properties:
+///
+/// * `path`: The source or working directory path of the file, asked for if not given.
+/// * `output`: The output format.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ExplainOptions {
+    pub path: Option<String>,
+    pub output: OutputFormat,
+}
+
+/// `LinkState` is an enum of the states of the link between the source of a file and its copy in
+/// the working directory. It's serialized in kebab case, e.g. `missing-source`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkState {
+    Excluded,
+    Adopted,
+    NotAdopted,
+    Missing,
+    MissingSource,
+    MissingCopy,
+    Linked,
+    NotLinked,
+}
+
+/// It's implementing the `Display` trait for the `LinkState` enum.
+impl fmt::Display for LinkState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkState::Excluded => write!(f, "excluded by an @exclude rule"),
+            LinkState::Adopted => write!(f, "adopted, the source links to the copy"),
+            LinkState::NotAdopted => write!(
+                f,
+                "not adopted yet, adopt to move it into the working directory"
+            ),
+            LinkState::Missing => write!(f, "missing at both ends"),
+            LinkState::MissingSource => write!(f, "missing at the source, deploy to restore it"),
+            LinkState::MissingCopy => {
+                write!(f, "not in the working directory yet, refresh to link it")
+            }
+            LinkState::Linked => write!(f, "linked"),
+            LinkState::NotLinked => {
+                write!(f, "not linked, the source and the copy are different files")
+            }
+        }
+    }
+}
+
+/// `GitState` is an enum of the git states of a copy in the working directory. It's serialized in
+/// kebab case, e.g. `not-in-working-dir`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GitState {
+    OutsideWorkingDir,
+    Conflict,
+    Ignored,
+    Untracked,
+    Staged,
+    Modified,
+    Committed,
+    NotInWorkingDir,
+}
+
+/// It's implementing the `Display` trait for the `GitState` enum.
+impl fmt::Display for GitState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GitState::OutsideWorkingDir => write!(f, "outside the working directory"),
+            GitState::Conflict => write!(f, "conflict"),
+            GitState::Ignored => write!(f, "ignored"),
+            GitState::Untracked => write!(f, "untracked"),
+            GitState::Staged => write!(f, "staged"),
+            GitState::Modified => write!(f, "modified"),
+            GitState::Committed => write!(f, "committed, unchanged"),
+            GitState::NotInWorkingDir => write!(f, "not in the working directory"),
+        }
+    }
+}
+
+/// `ExplainReport` is a struct that describes every mapping matching a path. It's the JSON output
+/// of `explain`.
+///
+/// Properties:
+///
+/// * `path`: The absolute path that was explained.
+/// * `matches`: The matching mappings, in the order of the config file.
+#[derive(Debug, Serialize)]
+struct ExplainReport {
+    path: PathBuf,
+    matches: Vec<ExplainMatch>,
+}
+
+/// `ExplainMatch` is a struct that describes how a mapping maps the explained path.
+///
+/// Properties:
+///
+/// * `config_file`: The name of the config file declaring the mapping.
+/// * `line`: The line of the mapping in the config file, if it was found.
+/// * `mapping`: The source pattern and destination of the mapping.
+/// * `groups`: The values captured by the groups of the source pattern, in order.
+/// * `source`: The source path of the file.
+/// * `destination`: The absolute path of the copy in the working directory.
+/// * `link`: The state of the link between the source and the copy.
+/// * `git`: The git state of the copy.
+#[derive(Debug, Serialize)]
+struct ExplainMatch {
+    config_file: &'static str,
+    line: Option<usize>,
+    mapping: MappingOutput,
+    groups: Vec<String>,
+    source: String,
+    destination: PathBuf,
+    link: LinkState,
+    git: GitState,
+}
+
+/// `MappingOutput` is a struct that describes a config mapping in JSON output.
+///
+/// Properties:
+///
+/// * `source`: The source pattern.
+/// * `destination`: The destination.
+#[derive(Debug, Serialize)]
+struct MappingOutput {
+    source: String,
+    destination: String,
+}
+
+/// `Explanation` is a struct that describes how a config mapping maps a single file.
+///
+/// Properties:
+///
+/// * `mapping`: The config mapping that matched the file.
+/// * `line`: The line of the config file declaring the mapping, if it was found.
+/// * `groups`: The values captured by the groups of the source pattern, in order.
+/// * `found`: The file mapped from its source to its destination.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    pub mapping: Mapping,
+    pub line: Option<usize>,
+    pub groups: Vec<String>,
+    pub found: Mapping,
+}
+
+/// It explains which mappings put a file where it is. The path may be either the source of the file
+/// or its copy in the working directory, and needn't exist.
+///
+/// Arguments:
+///
+/// * `target`: The path to explain.
+/// * `output`: The output format.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn explain(target: &str, output: OutputFormat) -> Result<(), GitError> {
+    let config_path = get_config_path();
+    let config =
+        load_config(&config_path).expect("Failed to load config file. Try initializing first.");
+    let path =
+        normalize_path(&path::absolute(target.trim()).unwrap_or_else(|_| PathBuf::from(target)));
+    let explanations = explain_path(&config_path, &config, &path);
+    let statuses = get_status()?;
+    if output == OutputFormat::Json {
+        let report = ExplainReport {
+            matches: explanations
+                .iter()
+                .map(|e| convert_explanation_to_match(&config, e, &statuses))
+                .collect(),
+            path,
+        };
+        print_json("explain", &report);
+        return Ok(());
+    }
+    if explanations.is_empty() {
+        println!("{:?} isn't matched by any mapping", &path);
+        return Ok(());
+    }
+    if explanations.len() > 1 {
+        println!("{:?} is matched by {} mappings", &path, explanations.len());
+    }
+    for explanation in &explanations {
+        print_explanation(&config, explanation, &statuses);
+    }
+    Ok(())
+}
+
+/// It finds every config mapping matching a path, either as the source of a file or as its copy in
+/// the working directory
+///
+/// Arguments:
+///
+/// * `config_path`: The path to the config file.
+/// * `config`: &Config
+/// * `path`: The absolute path to explain.
+///
+/// Returns:
+///
+/// A vector of Explanation structs, in the order of the config file
+fn explain_path(config_path: &str, config: &Config, path: &Path) -> Vec<Explanation> {
+    let mut explanations = Vec::new();
+    for mapping in &config.mappings {
+        let source = match find_entry(&mapping.source, path) {
+            Some(_) => Some(path.to_path_buf()),
+            None => find_source_of_copy(mapping, path),
+        };
+        let Some(entry) = source.and_then(|s| find_entry(&mapping.source, &s)) else {
+            continue;
+        };
+        let group_count = split_pattern_groups(&mapping.source)
+            .map(|(_, groups)| groups.len())
+            .unwrap_or(0);
+        explanations.push(Explanation {
+            mapping: mapping.clone(),
+            line: find_mapping_line(config_path, mapping).unwrap_or(None),
+            groups: (1..=group_count)
+                .filter_map(|n| entry.group(n))
+                .map(|g| g.to_string_lossy().to_string())
+                .collect(),
+            found: Mapping {
+                source: normalize_path(entry.path()).display().to_string(),
+                destination: substitute_group_values(mapping, &entry),
+                settings: mapping.settings.clone(),
+            },
+        });
+    }
+    explanations
+}
+
+/// It computes the source of a copy in the working directory by inverting a mapping
+///
+/// Arguments:
+///
+/// * `mapping`: The config mapping.
+/// * `path`: The absolute path of the copy.
+///
+/// Returns:
+///
+/// An Option of the source path
+fn find_source_of_copy(mapping: &Mapping, path: &Path) -> Option<PathBuf> {
+    let reversed = reverse_mapping(mapping)?;
+    let relative = path.strip_prefix(get_working_dir()).unwrap_or(path);
+    [path, relative]
+        .iter()
+        .find_map(|p| find_entry(&reversed.source, p))
+        .map(|entry| PathBuf::from(substitute_group_values(&reversed, &entry)))
+}
+
+/// It matches a path against a pattern. The groups of existing paths are taken from the files matched
+/// on disk, so they are the same as when refreshing. Other paths are matched against the pattern
+/// alone, with wildcards only matching within a path component unless the pattern contains `**`,
+/// like when matching files on disk.
+///
+/// Arguments:
+///
+/// * `pattern`: The pattern to match.
+/// * `path`: The path to match.
+///
+/// Returns:
+///
+/// An Option of the entry with the captured groups
+fn find_entry(pattern: &str, path: &Path) -> Option<Entry> {
+    if path.symlink_metadata().is_ok() {
+        return get_matching_files(pattern)
+            .ok()?
+            .into_iter()
+            .find(|e| e.path() == path);
+    }
+    let same_depth = Path::new(pattern).components().count() == path.components().count();
+    capture_path(pattern, path).filter(|_| same_depth || pattern.contains("**"))
+}
+
+/// It converts an explanation to its JSON output
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `explanation`: The explanation of the file.
+/// * `statuses`: The git statuses of the working directory.
+///
+/// Returns:
+///
+/// An ExplainMatch struct
+fn convert_explanation_to_match(
+    config: &Config,
+    explanation: &Explanation,
+    statuses: &GitStatusMap,
+) -> ExplainMatch {
+    let found = &explanation.found;
+    let copy = normalize_path(&resolve_destination(&found.destination));
+    ExplainMatch {
+        config_file: get_config_file(),
+        line: explanation.line,
+        mapping: MappingOutput {
+            source: explanation.mapping.source.clone(),
+            destination: explanation.mapping.destination.clone(),
+        },
+        groups: explanation.groups.clone(),
+        source: found.source.clone(),
+        link: find_link_state(config, found),
+        git: find_git_state(&copy, statuses),
+        destination: copy,
+    }
+}
+
+/// It prints where a mapping puts a file, along with the state of its link and its git status
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `explanation`: The explanation of the file.
+/// * `statuses`: The git statuses of the working directory.
+fn print_explanation(config: &Config, explanation: &Explanation, statuses: &GitStatusMap) {
+    let found = &explanation.found;
+    let copy = normalize_path(&resolve_destination(&found.destination));
+    let line = explanation
+        .line
+        .map(|l| format!("{}:{l}: ", get_config_file()))
+        .unwrap_or_default();
+    println!("{}", &found.source);
+    println!("  mapping:     {}{}", line, explanation.mapping);
+    for (index, group) in explanation.groups.iter().enumerate() {
+        println!("  group ({}):   {}", index + 1, group);
+    }
+    println!("  destination: {}", copy.display());
+    println!("  link:        {}", find_link_state(config, found));
+    println!("  git:         {}", find_git_state(&copy, statuses));
+}
+
+/// It finds whether the source of a file is linked to its copy in the working directory
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `found`: The found mapping of the file.
+///
+/// Returns:
+///
+/// A LinkState enum
+fn find_link_state(config: &Config, found: &Mapping) -> LinkState {
+    let source = Path::new(&found.source);
+    let copy = resolve_destination(&found.destination);
+    let source_exists = source.exists() || source.is_symlink();
+    let copy_exists = copy.exists() || copy.is_symlink();
+    if is_excluded(config, found) {
+        LinkState::Excluded
+    } else if is_directory_mapping(found) && is_adopted(found) {
+        LinkState::Adopted
+    } else if is_directory_mapping(found) {
+        LinkState::NotAdopted
+    } else if !source_exists && !copy_exists {
+        LinkState::Missing
+    } else if !source_exists {
+        LinkState::MissingSource
+    } else if !copy_exists {
+        LinkState::MissingCopy
+    } else if is_mapping_linked(found) {
+        LinkState::Linked
+    } else {
+        LinkState::NotLinked
+    }
+}
+
+/// It finds the git state of a copy in the working directory
+///
+/// Arguments:
+///
+/// * `copy`: The path of the copy.
+/// * `statuses`: The git statuses of the working directory.
+///
+/// Returns:
+///
+/// A GitState enum
+fn find_git_state(copy: &Path, statuses: &GitStatusMap) -> GitState {
+    let wdir = get_working_dir();
+    let Ok(relative) = copy.strip_prefix(&wdir) else {
+        return GitState::OutsideWorkingDir;
+    };
+    let status = statuses
+        .iter()
+        .find(|(path, _)| relative.starts_with(path) || path.starts_with(relative))
+        .map(|(_, status)| status);
+    match status {
+        Some(status) if status.is_conflicting() => GitState::Conflict,
+        Some(status) if status.is_ignored() => GitState::Ignored,
+        Some(status) if status.is_untracked() => GitState::Untracked,
+        Some(status) if status.is_staged() => GitState::Staged,
+        Some(_) => GitState::Modified,
+        None if copy.exists() || copy.is_symlink() => GitState::Committed,
+        None => GitState::NotInWorkingDir,
+    }
+}
+
+/// It removes the `.` and `..` components and repeated separators of a path without resolving
+/// symbolic links, which would hide the links being explained
+///
+/// Arguments:
+///
+/// * `path`: The path to normalize.
+///
+/// Returns:
+///
+/// A PathBuf
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
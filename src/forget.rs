@@ -0,0 +1,199 @@
+use log::{info, warn};
+use same_file::is_same_file;
+use std::fs;
+use std::path::{self, Path, PathBuf};
+use std::process;
+
+use crate::adopt::{is_adopted, is_directory_mapping, undo_adoption};
+use crate::config::{
+    append_setting, get_config_path, load_config, remove_mapping_line, Config, Mapping,
+};
+use crate::fs::{copy_symlink, escape_pattern, remove_from_fs};
+use crate::git::{rm_cached, GitError};
+use crate::process::{get_tracked_mappings, resolve_destination};
+
+/// It stops tracking a mapping or files. A mapping is given by its source pattern or its whole line,
+/// and files by their source or working directory paths. The source of every forgotten file is made a
+/// standalone file before its copy is removed from the working directory and the index. Forgotten
+/// mappings are removed from the config file, while forgotten files of pattern mappings get an
+/// exclude rule.
+///
+/// Arguments:
+///
+/// * `target`: The mapping or path to forget.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn forget(target: &str) -> Result<(), GitError> {
+    let config_path = get_config_path();
+    let config =
+        load_config(&config_path).expect("Failed to load config file. Try initializing first.");
+    let target_path = path::absolute(target.trim()).unwrap_or_else(|_| PathBuf::from(target));
+    let declared = config
+        .mappings
+        .iter()
+        .filter(|m| {
+            m.source == target.trim()
+                || format!("{} : {}", m.source, m.destination) == target.trim()
+                || Path::new(&m.source) == target_path
+        })
+        .cloned()
+        .collect::<Vec<Mapping>>();
+    let files = if declared.is_empty() {
+        get_tracked_mappings(&config)
+            .into_iter()
+            .filter(|m| {
+                Path::new(&m.source).starts_with(&target_path)
+                    || resolve_destination(&m.destination).starts_with(&target_path)
+            })
+            .collect::<Vec<Mapping>>()
+    } else {
+        declared.iter().flat_map(find_tracked_matches).collect()
+    };
+    if declared.is_empty() && files.is_empty() {
+        warn!("{target:?} is neither a mapping nor a tracked file");
+        return Ok(());
+    }
+    for file in &files {
+        detach_source(file)?;
+        let copy = resolve_destination(&file.destination);
+        if copy.exists() || copy.is_symlink() {
+            rm_cached(&copy.display().to_string())?;
+            remove_from_fs(&copy);
+            info!("Removed {:?} from the working directory", &copy);
+        }
+    }
+    if !declared.is_empty() {
+        remove_mappings(&config_path, &declared);
+        return Ok(());
+    }
+    let mut literal = Vec::new();
+    let mut matched = Vec::new();
+    for file in files {
+        match find_declaring_mapping(&config, &file) {
+            Some(mapping) if !literal.contains(&mapping) => literal.push(mapping),
+            Some(_) => {}
+            None => matched.push(file),
+        }
+    }
+    remove_mappings(&config_path, &literal);
+    exclude_files(&config_path, &matched);
+    Ok(())
+}
+
+/// It finds the files of a config mapping, both those at the source locations and those only in the
+/// working directory
+///
+/// Arguments:
+///
+/// * `mapping`: The config mapping.
+///
+/// Returns:
+///
+/// A vector of Mapping structs
+fn find_tracked_matches(mapping: &Mapping) -> Vec<Mapping> {
+    get_tracked_mappings(&Config {
+        mappings: vec![mapping.clone()],
+        ..Config::default()
+    })
+}
+
+/// It returns the config mapping declared for exactly this file, i.e. one whose source is the path of
+/// the file rather than a pattern
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `file`: The found mapping of the file.
+///
+/// Returns:
+///
+/// An Option<Mapping>
+fn find_declaring_mapping(config: &Config, file: &Mapping) -> Option<Mapping> {
+    config
+        .mappings
+        .iter()
+        .find(|m| m.source == file.source)
+        .cloned()
+}
+
+/// It makes sure the source of a file is standalone rather than a link into the working directory,
+/// restoring it from its copy if it's missing. Symbolic links tracked as such are standalone already.
+///
+/// Arguments:
+///
+/// * `file`: The found mapping of the file.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+fn detach_source(file: &Mapping) -> Result<(), GitError> {
+    let source = PathBuf::from(&file.source);
+    let copy = resolve_destination(&file.destination);
+    if is_directory_mapping(file) {
+        if is_adopted(file) {
+            undo_adoption(file)?;
+        }
+        return Ok(());
+    }
+    if source.is_symlink() {
+        return Ok(());
+    }
+    if source.exists() {
+        if is_same_file(&source, &copy).unwrap_or(false) {
+            let name = source.file_name().unwrap_or_default().to_string_lossy();
+            let detached =
+                source.with_file_name(format!(".{}.configman-detached-{}", name, process::id()));
+            fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&detached)
+                .unwrap_or_else(|e| panic!("Failed to create {:?}: {:?}", &detached, e));
+            fs::copy(&source, &detached)
+                .and_then(|_| fs::rename(&detached, &source))
+                .unwrap_or_else(|_| panic!("Failed to detach {:?} from {:?}", &source, &copy));
+            info!("Detached {:?} from the working directory", &source);
+        }
+    } else if copy.is_symlink() || copy.is_file() {
+        source.parent().map(fs::create_dir_all);
+        if copy.is_symlink() {
+            copy_symlink(&copy, &source);
+        } else {
+            fs::copy(&copy, &source)
+                .unwrap_or_else(|_| panic!("Failed to restore {:?} from {:?}", &source, &copy));
+        }
+        info!("Restored {:?} from the working directory", &source);
+    }
+    Ok(())
+}
+
+/// It removes the forgotten mappings from the config file
+///
+/// Arguments:
+///
+/// * `config_path`: The path to the config file.
+/// * `mappings`: The mappings to remove.
+fn remove_mappings(config_path: &str, mappings: &[Mapping]) {
+    for mapping in mappings {
+        match remove_mapping_line(config_path, mapping) {
+            Ok(true) => info!("Removed `{mapping}` from the config file"),
+            Ok(false) => warn!("Failed to find `{mapping}` in the config file"),
+            Err(e) => panic!("Failed to remove mapping from config file: {:?}", e),
+        }
+    }
+}
+
+/// It excludes files matched by pattern mappings from now on
+///
+/// Arguments:
+///
+/// * `config_path`: The path to the config file.
+/// * `files`: The found mappings of the files.
+fn exclude_files(config_path: &str, files: &[Mapping]) {
+    for file in files {
+        append_setting(config_path, "exclude", &escape_pattern(&file.source))
+            .unwrap_or_else(|e| panic!("Failed to add exclude rule to config file: {:?}", e));
+        info!("Excluded {} from now on", &file.source);
+    }
+}
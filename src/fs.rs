@@ -1,7 +1,14 @@
-use crate::git::{get_file_status, rm_file};
-use capturing_glob::{glob_with, Entry, MatchOptions, PatternError};
-use indicatif::{ProgressBar, ProgressIterator};
-use std::{env, fs, path::PathBuf};
+use crate::backup::back_up;
+use crate::config::{Mapping, CONFIG_FILE, TOML_CONFIG_FILE};
+use crate::git::{get_status, rm_file, GitError, GitFileStatus, GitStatusMap};
+use crate::journal::{keep_removed, record, JournalEntry};
+use crate::metadata::METADATA_FILE;
+use crate::output::with_progress_bar;
+use capturing_glob::{glob_with, Entry, MatchOptions, Pattern, PatternError};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
 
 /// Setting the options for the globbing.
 const OPTIONS: MatchOptions = MatchOptions {
@@ -10,6 +17,44 @@ const OPTIONS: MatchOptions = MatchOptions {
     require_literal_leading_dot: false,
 };
 
+/// `SymlinkPolicy` is an enum of the ways a mapping handles symbolic links among its matched files,
+/// declared as `@symlinks = follow|track|skip`. Without one, links to files are hard-linked as they
+/// are and links to directories are left out.
+///
+/// * `Follow`: The file the link points to is tracked, if it can be hard-linked.
+/// * `Track`: The link itself is tracked, and recreated with the same target on deploy.
+/// * `Skip`: Links are left out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    Follow,
+    Track,
+    Skip,
+}
+
+impl SymlinkPolicy {
+    /// It returns the symlink policy declared on a mapping, if any
+    ///
+    /// Arguments:
+    ///
+    /// * `mapping`: The mapping.
+    ///
+    /// Returns:
+    ///
+    /// An Option of a SymlinkPolicy enum
+    pub fn of(mapping: &Mapping) -> Option<SymlinkPolicy> {
+        match mapping.settings_named("symlinks").last() {
+            None => None,
+            Some(&"follow") => Some(SymlinkPolicy::Follow),
+            Some(&"track") => Some(SymlinkPolicy::Track),
+            Some(&"skip") => Some(SymlinkPolicy::Skip),
+            Some(policy) => panic!(
+                "{:?} is not a valid symlink policy of {}. Expected follow, track or skip",
+                policy, mapping
+            ),
+        }
+    }
+}
+
 /// It takes a glob pattern, and returns a list of files that match that pattern
 ///
 /// Arguments:
@@ -23,6 +68,64 @@ pub fn get_matching_files(pattern: &str) -> Result<Vec<Entry>, PatternError> {
     glob_with(pattern, &OPTIONS).map(|ps| ps.map(|p| p.unwrap()).collect::<Vec<Entry>>())
 }
 
+/// It matches a single path against a glob pattern, whether or not the path exists
+///
+/// Arguments:
+///
+/// * `pattern`: The pattern to match.
+/// * `path`: The path to match.
+///
+/// Returns:
+///
+/// An Option of the entry with the captured groups
+pub fn capture_path(pattern: &str, path: &Path) -> Option<Entry> {
+    Pattern::new(pattern)
+        .ok()
+        .and_then(|p| p.captures_path_with(path, &OPTIONS))
+}
+
+/// It escapes a path so that it can be used as a pattern matching only itself, wildcards and capture
+/// groups included
+///
+/// Arguments:
+///
+/// * `path`: The path.
+///
+/// Returns:
+///
+/// A String
+pub fn escape_pattern(path: &str) -> String {
+    Pattern::escape(path)
+        .replace('(', "[(]")
+        .replace(')', "[)]")
+}
+
+/// It returns the longest leading directory of a glob pattern that contains no wildcards or capture
+/// groups, i.e. the directory every match of the pattern lives under
+///
+/// Arguments:
+///
+/// * `pattern`: The glob pattern.
+///
+/// Returns:
+///
+/// A PathBuf
+pub fn get_pattern_root(pattern: &str) -> PathBuf {
+    let mut root = PathBuf::new();
+    let mut components = Path::new(pattern).components().peekable();
+    while let Some(component) = components.next() {
+        let literal = !component
+            .as_os_str()
+            .to_string_lossy()
+            .contains(['*', '?', '[', '(']);
+        if !literal || components.peek().is_none() {
+            break;
+        }
+        root.push(component);
+    }
+    root
+}
+
 /// `get_working_dir()` returns the current working directory
 ///
 /// Returns:
@@ -32,41 +135,136 @@ pub fn get_working_dir() -> PathBuf {
     env::current_dir().expect("Failed to get current working directory.")
 }
 
-/// It removes all files and directories from the working directory except for the `config.cmf` file,
-/// the `.git` directory and any files ignored by git
-pub fn clean_working_dir() {
+/// It removes all files and directories from the working directory except for the config files, the
+/// `.git` directory, any files ignored by git and the kept paths, e.g. adopted directories. The files
+/// that would be lost are backed up first.
+///
+/// Arguments:
+///
+/// * `keep`: The paths in the working directory to keep along with their contents.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn clean_working_dir(keep: &[PathBuf]) -> Result<(), GitError> {
+    let removed = find_cleaned_paths(keep)?;
+    back_up("clean", &removed);
+    for entry_path in with_progress_bar(removed.iter(), "Cleaning working directory") {
+        remove_from_fs(entry_path);
+        rm_file(&entry_path.display().to_string())?;
+    }
+    Ok(())
+}
+
+/// It finds the files and directories `clean_working_dir` removes from the working directory
+///
+/// Arguments:
+///
+/// * `keep`: The paths in the working directory to keep along with their contents.
+///
+/// Returns:
+///
+/// A Result of a vector of the paths to remove
+pub fn find_cleaned_paths(keep: &[PathBuf]) -> Result<Vec<PathBuf>, GitError> {
     let wdir = get_working_dir();
-    let skip = vec![
-        wdir.join("config.cmf"),
+    let mut skip = vec![
+        wdir.join(CONFIG_FILE),
+        wdir.join(TOML_CONFIG_FILE),
         wdir.join(".git"),
         wdir.join("LICENSE"),
+        wdir.join(METADATA_FILE),
     ];
+    skip.extend(keep.iter().cloned());
+    let statuses = get_status()?;
     let rdir = wdir.read_dir().expect("Failed read from working directory");
-    for entry in
-        rdir.progress_with(ProgressBar::new_spinner().with_message("Cleaning working directory"))
-    {
-        if let Ok(dir_entry) = entry {
-            let entry_path = dir_entry.path();
-            let ignored = skip.contains(&entry_path)
-                || get_file_status(&entry_path.display().to_string()).is_ignored();
-            if !ignored {
-                remove_from_fs(&entry_path);
-                rm_file(&entry_path.display().to_string());
-            }
+    let mut removed = Vec::new();
+    for dir_entry in rdir.flatten() {
+        find_cleaned_entries(&dir_entry.path(), &wdir, &skip, &statuses, &mut removed);
+    }
+    Ok(removed)
+}
+
+/// It lists the files below a path, symbolic links included but not followed, or the path itself if
+/// it isn't a directory
+///
+/// Arguments:
+///
+/// * `path`: The path to a file or directory.
+///
+/// Returns:
+///
+/// A vector of PathBufs
+pub fn list_files(path: &Path) -> Vec<PathBuf> {
+    if !path.is_dir() || path.is_symlink() {
+        return vec![path.to_path_buf()];
+    }
+    let mut files = path
+        .read_dir()
+        .unwrap_or_else(|_| panic!("Failed read from {:?}", &path))
+        .flatten()
+        .flat_map(|entry| list_files(&entry.path()))
+        .collect::<Vec<PathBuf>>();
+    files.sort();
+    files
+}
+
+/// It finds the entries of the working directory to remove, i.e. the entry itself unless it's
+/// skipped or ignored by git. Directories containing skipped paths are searched entry by entry
+/// instead.
+///
+/// Arguments:
+///
+/// * `entry_path`: The path of the entry.
+/// * `wdir`: The working directory.
+/// * `skip`: The paths to keep.
+/// * `statuses`: The git statuses of the working directory.
+/// * `removed`: The entries to remove.
+fn find_cleaned_entries(
+    entry_path: &PathBuf,
+    wdir: &Path,
+    skip: &[PathBuf],
+    statuses: &GitStatusMap,
+    removed: &mut Vec<PathBuf>,
+) {
+    let ignored = skip.contains(entry_path)
+        || entry_path
+            .strip_prefix(wdir)
+            .ok()
+            .and_then(|p| statuses.get(p))
+            .is_some_and(GitFileStatus::is_ignored);
+    if ignored {
+        return;
+    }
+    let contains_skipped = skip.iter().any(|s| s.starts_with(entry_path));
+    if contains_skipped && entry_path.is_dir() && !entry_path.is_symlink() {
+        for dir_entry in entry_path
+            .read_dir()
+            .unwrap_or_else(|_| panic!("Failed read from {:?}", &entry_path))
+            .flatten()
+        {
+            find_cleaned_entries(&dir_entry.path(), wdir, skip, statuses, removed);
         }
+    } else {
+        removed.push(entry_path.clone());
     }
 }
 
-/// It removes a file or directory from the filesystem
+/// It removes a file or directory from the filesystem. While an operation is journaled, it's moved
+/// into the journal instead so that it can be put back.
 ///
 /// Arguments:
 ///
 /// * `path`: The path to the file or directory to remove.
 pub fn remove_from_fs(path: &PathBuf) {
-    if path.is_dir() {
-        fs::remove_dir_all(&path).ok();
+    if keep_removed(path) {
+        return;
+    }
+    if path.is_symlink() {
+        fs::remove_file(path).ok();
+    } else if path.is_dir() {
+        fs::remove_dir_all(path).ok();
     } else if path.exists() {
-        fs::remove_file(&path).ok();
+        fs::remove_file(path).ok();
     }
 }
 
@@ -82,5 +280,99 @@ pub fn link_path(original: &PathBuf, link: &PathBuf) {
     } else {
         fs::hard_link(original, link)
     }
-    .expect(format!("Failed to link to {:?} as {:?}", &original, &link).as_str())
+    .unwrap_or_else(|_| panic!("Failed to link to {:?} as {:?}", &original, &link));
+    record(JournalEntry::Linked(link.clone()));
+}
+
+/// It hard-links a file in place of the link path without removing what's there until the new link
+/// exists, so that nothing is lost if linking fails, e.g. since the file is on another file system
+///
+/// Arguments:
+///
+/// * `original`: The path to the original file.
+/// * `link`: The path to the link to be created or replaced.
+///
+/// Returns:
+///
+/// A Result<(), std::io::Error>
+pub fn try_hard_link(original: &PathBuf, link: &PathBuf) -> std::io::Result<()> {
+    link.parent().map(fs::create_dir_all);
+    let name = link.file_name().unwrap_or_default().to_string_lossy();
+    let staged = link.with_file_name(format!(".{}.configman-link", name));
+    fs::remove_file(&staged).ok();
+    fs::hard_link(original, &staged)
+        .and_then(|_| fs::rename(&staged, link))
+        .inspect_err(|_| {
+            fs::remove_file(&staged).ok();
+        })?;
+    record(JournalEntry::Linked(link.clone()));
+    Ok(())
+}
+
+/// It creates a symbolic link with the same target as another one
+///
+/// Arguments:
+///
+/// * `original`: The path to the symbolic link to copy.
+/// * `link`: The path to the link to be created.
+pub fn copy_symlink(original: &PathBuf, link: &PathBuf) {
+    let target = fs::read_link(original)
+        .unwrap_or_else(|_| panic!("Failed to read the target of {:?}", &original));
+    std::os::unix::fs::symlink(&target, link)
+        .unwrap_or_else(|_| panic!("Failed to link to {:?} as {:?}", &target, &link));
+    record(JournalEntry::Linked(link.clone()));
+}
+
+/// It checks whether two paths are symbolic links with the same target
+///
+/// Arguments:
+///
+/// * `a`: The path to the first link.
+/// * `b`: The path to the second link.
+///
+/// Returns:
+///
+/// A boolean value.
+pub fn is_same_symlink(a: &Path, b: &Path) -> bool {
+    a.is_symlink() && b.is_symlink() && fs::read_link(a).ok() == fs::read_link(b).ok()
+}
+
+/// It moves a directory, copying it over and removing the original when it can't simply be renamed,
+/// e.g. across file systems
+///
+/// Arguments:
+///
+/// * `from`: The directory to move.
+/// * `to`: The new path of the directory.
+pub fn move_dir(from: &PathBuf, to: &PathBuf) {
+    to.parent().map(fs::create_dir_all);
+    if fs::rename(from, to).is_err() {
+        copy_dir(from, to);
+        fs::remove_dir_all(from).unwrap_or_else(|_| panic!("Failed to remove {:?}", &from));
+    }
+}
+
+/// It copies a directory recursively, keeping symbolic links as they are
+///
+/// Arguments:
+///
+/// * `from`: The directory to copy.
+/// * `to`: The path of the copy.
+fn copy_dir(from: &PathBuf, to: &PathBuf) {
+    fs::create_dir_all(to).unwrap_or_else(|_| panic!("Failed to create {:?}", &to));
+    for entry in from
+        .read_dir()
+        .unwrap_or_else(|_| panic!("Failed read from {:?}", &from))
+        .flatten()
+    {
+        let (source, target) = (entry.path(), to.join(entry.file_name()));
+        if source.is_symlink() {
+            copy_symlink(&source, &target);
+        } else if source.is_dir() {
+            copy_dir(&source, &target);
+        } else {
+            fs::copy(&source, &target)
+                .unwrap_or_else(|_| panic!("Failed to copy {:?} to {:?}", &source, &target));
+        }
+    }
 }
@@ -1,20 +1,147 @@
-use std::io::{BufRead, BufReader, Error, ErrorKind};
-use std::process::{Command, Output, Stdio};
+use log::{debug, info, trace, warn};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::Error;
+use std::path::PathBuf;
+use std::process::{Command, Output};
 
-/// `GitFileStatus` is a struct that contains two fields, `index_status` and `working_tree_status`, both
-/// of which are characters.
+use crate::backup::back_up;
+use crate::fs::{get_working_dir, remove_from_fs};
+use crate::journal::{record, JournalEntry};
+use crate::output::OutputFormat;
+
+/// `GitFileStatus` is a struct that describes a single entry reported by `git status`.
 ///
 /// Properties:
 ///
 /// * `index_status`: The status of the file in the index.
 /// * `working_tree_status`: The status of the file in the working tree.
-#[derive(Debug)]
+/// * `original_path`: The path the file was renamed or copied from, if any.
+/// * `submodule_state`: The `S<c><m><u>` submodule field, if the entry is a submodule.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct GitFileStatus {
     pub index_status: char,
     pub working_tree_status: char,
+    pub original_path: Option<PathBuf>,
+    pub submodule_state: Option<String>,
+}
+
+/// `GitStatusMap` maps paths, relative to the repository root, to their `GitFileStatus`.
+pub type GitStatusMap = HashMap<PathBuf, GitFileStatus>;
+
+/// `FetchStrategy` decides how fetched commits are applied to the local branch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FetchStrategy {
+    #[default]
+    Reset,
+    Rebase,
+    Merge,
+}
+
+/// `FetchOptions` is a struct that describes where to fetch from and how to apply the result.
+///
+/// Properties:
+///
+/// * `strategy`: How the fetched commits are applied to the local branch.
+/// * `remote`: The remote to fetch from, defaults to the upstream of the current branch.
+/// * `branch`: The remote branch to apply, defaults to the upstream of the current branch.
+/// * `force`: Whether a hard reset may discard uncommitted changes and local commits.
+/// * `discard_changes`: Whether a hard reset may discard uncommitted changes, but not local commits.
+/// * `dry_run`: Whether to only report what applying the fetched commits would change.
+/// * `output`: The format the result is reported in.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FetchOptions {
+    pub strategy: FetchStrategy,
+    pub remote: Option<String>,
+    pub branch: Option<String>,
+    pub force: bool,
+    pub discard_changes: bool,
+    pub dry_run: bool,
+    pub output: OutputFormat,
+}
+
+/// `GitError` is an enum of the ways a git command can fail.
+///
+/// * `Spawn`: git couldn't be run at all, e.g. because it isn't installed.
+/// * `Failed`: git ran but exited with a non-zero status or was killed by a signal, in which case
+///   `code` is `None`.
+#[derive(Debug)]
+pub enum GitError {
+    Spawn {
+        command: String,
+        error: Error,
+    },
+    Failed {
+        command: String,
+        code: Option<i32>,
+        stderr: String,
+    },
 }
 
+/// It's implementing the `Display` trait for the `GitError` enum.
+impl fmt::Display for GitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GitError::Spawn { command, error } => write!(f, "Failed to run git {command}: {error}"),
+            GitError::Failed {
+                command,
+                code,
+                stderr,
+            } => {
+                match code {
+                    Some(code) => write!(f, "git {command} failed with exit code {code}")?,
+                    None => write!(f, "git {command} was terminated by a signal")?,
+                }
+                match stderr.trim_end() {
+                    "" => Ok(()),
+                    stderr => write!(f, ":\n{stderr}"),
+                }
+            }
+        }
+    }
+}
+
+impl std::error::Error for GitError {}
+
+/// `ConflictSide` names which version of a conflicting file should be kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictSide {
+    Ours,
+    Theirs,
+}
+
+/// The unmerged `XY` combinations git reports for conflicting files.
+const UNMERGED_STATES: [(char, char); 7] = [
+    ('A', 'A'),
+    ('A', 'U'),
+    ('D', 'D'),
+    ('D', 'U'),
+    ('U', 'A'),
+    ('U', 'D'),
+    ('U', 'U'),
+];
+
 impl GitFileStatus {
+    /// It creates a status from the two characters of a porcelain `XY` field. Porcelain v2 uses `.`
+    /// for unchanged sides, which is normalised to a space to match the short format.
+    ///
+    /// Arguments:
+    ///
+    /// * `xy`: The `XY` field of a status entry.
+    ///
+    /// Returns:
+    ///
+    /// A GitFileStatus
+    fn from_xy(xy: &str) -> GitFileStatus {
+        let mut chars = xy.chars().map(|c| if c == '.' { ' ' } else { c });
+        GitFileStatus {
+            index_status: chars.next().unwrap_or(' '),
+            working_tree_status: chars.next().unwrap_or(' '),
+            original_path: None,
+            submodule_state: None,
+        }
+    }
+
     /// If the index status and the working tree status are both `!`, then the file is ignored
     ///
     /// Returns:
@@ -23,9 +150,37 @@ impl GitFileStatus {
     pub const fn is_ignored(&self) -> bool {
         self.index_status == '!' && self.working_tree_status == '!'
     }
+
+    /// If the index status and the working tree status are both `?`, then the file is untracked
+    ///
+    /// Returns:
+    ///
+    /// A boolean value.
+    pub const fn is_untracked(&self) -> bool {
+        self.index_status == '?' && self.working_tree_status == '?'
+    }
+
+    /// If the index status is anything other than unchanged, untracked or ignored, then the file has
+    /// staged changes
+    ///
+    /// Returns:
+    ///
+    /// A boolean value.
+    pub fn is_staged(&self) -> bool {
+        !self.is_conflicting() && !matches!(self.index_status, ' ' | '?' | '!')
+    }
+
+    /// If the `XY` pair is one of the unmerged combinations, then the file is conflicting
+    ///
+    /// Returns:
+    ///
+    /// A boolean value.
+    pub fn is_conflicting(&self) -> bool {
+        UNMERGED_STATES.contains(&(self.index_status, self.working_tree_status))
+    }
 }
 
-/// `exec_git` takes a vector of strings and returns a `Result` of `Output` or `Error`
+/// It runs the `git` command, logging it at the debug level, and waits for its output
 ///
 /// Arguments:
 ///
@@ -33,173 +188,1078 @@ impl GitFileStatus {
 ///
 /// Returns:
 ///
-/// A Result<Output, Error>
-pub fn exec_git(arg: Vec<&str>) -> Result<Output, Error> {
-    Command::new("git").args(arg).output()
+/// A Result of a tuple of the command line and its Output, or a GitError if git couldn't be run
+fn spawn_git(arg: Vec<&str>) -> Result<(String, Output), GitError> {
+    let command = arg.join(" ");
+    debug!("Running git {command}");
+    match Command::new("git").args(arg).output() {
+        Ok(output) => Ok((command, output)),
+        Err(error) => Err(GitError::Spawn { command, error }),
+    }
 }
 
-/// It executes the `git` command, captures its standard output, and prints each line of the output to
-/// the console
+/// It turns a non-zero exit status of a git command into a `GitError::Failed` carrying its standard
+/// error
 ///
 /// Arguments:
 ///
-/// * `arg`: Vec<&str> - This is the vector of arguments that we want to pass to the git command.
+/// * `command`: The command line, without `git`.
+/// * `output`: The output of the command.
+///
+/// Returns:
+///
+/// A Result<Output, GitError>
+fn check_status(command: String, output: Output) -> Result<Output, GitError> {
+    if output.status.success() {
+        return Ok(output);
+    }
+    debug!("git {command} failed with {}", output.status);
+    Err(GitError::Failed {
+        command,
+        code: output.status.code(),
+        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+    })
+}
+
+/// `exec_git` takes a vector of strings and returns a `Result` of `Output` or `GitError`. Its output is
+/// logged at the trace level, and a non-zero exit status is an error.
+///
+/// Arguments:
+///
+/// * `arg`: Vec<&str> - A vector of arguments to pass to the git command.
 ///
 /// Returns:
 ///
-/// Result<(), Error>
-pub fn exec_git_with_logs(arg: Vec<&str>) -> Result<(), Error> {
-    let stdout = Command::new("git")
-        .args(arg)
-        .stdout(Stdio::piped())
-        .spawn()?
-        .stdout
-        .ok_or_else(|| Error::new(ErrorKind::Other, "Could not capture standard output."))?;
+/// A Result<Output, GitError>
+pub fn exec_git(arg: Vec<&str>) -> Result<Output, GitError> {
+    let (command, output) = spawn_git(arg)?;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        trace!("git: {line}");
+    }
+    check_status(command, output)
+}
 
-    BufReader::new(stdout)
-        .lines()
-        .filter_map(|line| line.ok())
-        .for_each(|line| println!("{}", line));
+/// It executes the `git` command and logs each line of its standard output, whether it succeeds or
+/// not. Its standard error is part of the error if it fails, and logged at the debug level otherwise.
+///
+/// Arguments:
+///
+/// * `arg`: Vec<&str> - This is the vector of arguments that we want to pass to the git command.
+///
+/// Returns:
+///
+/// Result<(), GitError>
+pub fn exec_git_with_logs(arg: Vec<&str>) -> Result<(), GitError> {
+    let (command, output) = spawn_git(arg)?;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        info!("{line}");
+    }
+    let output = check_status(command, output)?;
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        debug!("git: {line}");
+    }
     Ok(())
 }
 
-/// If the output of `git rev-parse --git-dir` is `.git`, then we're in a git repo
+/// It executes a git command and returns its trimmed standard output if the command succeeded and
+/// printed anything
+///
+/// Arguments:
+///
+/// * `arg`: Vec<&str> - A vector of arguments to pass to the git command.
 ///
 /// Returns:
 ///
-/// A boolean value.
-pub fn is_git_repo_root_dir() -> bool {
-    exec_git(vec!["rev-parse", "--git-dir"])
-        .map(
-            |output| match std::str::from_utf8(&output.stdout).unwrap().trim() {
-                ".git" => true,
-                _ => false,
-            },
-        )
-        .expect("Failed to determine if current directory is a git repo")
+/// An Option<String>
+pub fn read_git(arg: Vec<&str>) -> Option<String> {
+    exec_git(arg)
+        .ok()
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|out| !out.is_empty())
+}
+
+/// If the output of `git rev-parse --git-dir` is `.git`, then we're in a git repo. Outside of any repo
+/// the command fails, which also means we aren't.
+///
+/// Returns:
+///
+/// A Result of a boolean value, or the GitError if git couldn't be run
+pub fn is_git_repo_root_dir() -> Result<bool, GitError> {
+    match exec_git(vec!["rev-parse", "--git-dir"]) {
+        Ok(output) => Ok(String::from_utf8_lossy(&output.stdout).trim() == ".git"),
+        Err(GitError::Failed { .. }) => Ok(false),
+        Err(e) => Err(e),
+    }
 }
 
 /// It executes the `git init` command
-pub fn init_git() {
-    exec_git_with_logs(vec!["init"]).expect("Failed to initialize git repo here");
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn init_git() -> Result<(), GitError> {
+    exec_git_with_logs(vec!["init"])
+}
+
+/// It points a remote at the given URL, adding the remote if it doesn't exist yet
+///
+/// Arguments:
+///
+/// * `name`: The name of the remote.
+/// * `url`: The URL of the remote repo.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn set_remote(name: &str, url: &str) -> Result<(), GitError> {
+    let action = match read_git(vec!["remote", "get-url", name]) {
+        Some(_) => "set-url",
+        None => "add",
+    };
+    exec_git_with_logs(vec!["remote", action, name, url])
 }
 
-/// It adds a file to the index if it's not already added
+/// It renames a remote, keeping the branches that track it in sync
 ///
 /// Arguments:
 ///
-/// * `path`: &str
-pub fn add_file(paths: &Vec<String>) {
+/// * `name`: The current name of the remote.
+/// * `new_name`: The new name of the remote.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn rename_remote(name: &str, new_name: &str) -> Result<(), GitError> {
+    if name == new_name {
+        return Ok(());
+    }
+    exec_git_with_logs(vec!["remote", "rename", name, new_name])
+}
+
+/// It lists the configured remotes along with their URLs
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn list_remotes() -> Result<(), GitError> {
+    exec_git_with_logs(vec!["remote", "-v"])
+}
+
+/// It clones an existing repo into the working directory, which unlike `git clone` also works when
+/// the directory already contains files, e.g. a previously generated config file. Untracked files
+/// that the repo would overwrite are backed up and removed first. An empty repo is only set up as the
+/// remote, and the first push sets the upstream of the branch.
+///
+/// Arguments:
+///
+/// * `url`: The URL of the repo, `file://` URLs and local paths included.
+/// * `remote`: The name to give the remote.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn clone_into_working_dir(url: &str, remote: &str) -> Result<(), GitError> {
+    if !is_git_repo_root_dir()? {
+        init_git()?;
+    }
+    set_remote(remote, url)?;
+    exec_git_with_logs(vec!["fetch", remote])?;
+    let Some(branch) = get_remote_default_branch(remote)
+        .or_else(|| get_remote_branches(remote).into_iter().next())
+    else {
+        info!("The remote repo is empty. The first push will set up its branch.");
+        return Ok(());
+    };
+    remove_untracked_conflicts(&format!("{remote}/{branch}"))?;
+    exec_git_with_logs(vec![
+        "checkout",
+        "-B",
+        &branch,
+        "--track",
+        &format!("{remote}/{branch}"),
+    ])
+}
+
+/// It backs up and removes the untracked files that checking out a commit would overwrite, since git
+/// refuses to overwrite them
+///
+/// Arguments:
+///
+/// * `commit`: The commit about to be checked out.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+fn remove_untracked_conflicts(commit: &str) -> Result<(), GitError> {
+    let wdir = get_working_dir();
+    let list = |arg: Vec<&str>| {
+        exec_git(arg).map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(String::from)
+                .collect::<HashSet<String>>()
+        })
+    };
+    let incoming = list(vec!["ls-tree", "-r", "--name-only", commit])?;
+    let conflicts = list(vec!["ls-files", "--others"])?
+        .intersection(&incoming)
+        .map(|path| wdir.join(path))
+        .collect::<Vec<PathBuf>>();
+    if conflicts.is_empty() {
+        return Ok(());
+    }
+    back_up("clone", &conflicts);
+    for path in &conflicts {
+        remove_from_fs(path);
+        info!("Removed untracked {:?}, since {commit} replaces it", path);
+    }
+    Ok(())
+}
+
+/// It adds files to the index. Ignored paths make git fail, so they should be left out beforehand
+///
+/// Arguments:
+///
+/// * `paths`: The paths of the files to add.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn add_file(paths: &[String]) -> Result<(), GitError> {
     let mut arg = vec!["add"];
     arg.extend(paths.iter().map(|s| s.as_str()));
-    exec_git(arg).expect(format!("Failed to index {:?}", &paths).as_str());
+    exec_git(arg)?;
+    for path in paths {
+        record(JournalEntry::Indexed(path.clone()));
+    }
+    Ok(())
 }
 
-/// It takes a path as a string, and stages only modified and deleted files.
-pub fn stage_files() {
-    exec_git_with_logs(vec!["add", "-u"]).expect("Failed to stage changes");
+/// It stages the given files, including deletions
+///
+/// Arguments:
+///
+/// * `paths`: The paths of the files to stage.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn stage_files(paths: &[String]) -> Result<(), GitError> {
+    if paths.is_empty() {
+        return Ok(());
+    }
+    let mut arg = vec!["add", "-A", "--"];
+    arg.extend(paths.iter().map(|s| s.as_str()));
+    exec_git_with_logs(arg)
 }
 
-/// It removes a file from the index
+/// It removes a file or directory from the index, doing nothing if it isn't indexed
 ///
 /// Arguments:
 ///
 /// * `path`: The path to the file to be removed.
-pub fn rm_file(path: &str) {
-    exec_git(vec!["rm", &path]).expect(format!("Failed to unindex {}", &path).as_str());
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn rm_file(path: &str) -> Result<(), GitError> {
+    exec_git(vec!["rm", "-r", "--quiet", "--ignore-unmatch", "--", path])?;
+    record(JournalEntry::Unindexed(path.to_string()));
+    Ok(())
 }
 
-/// It runs `git status -s <path>` and parses the output
+/// It removes a file or directory from the index, keeping it on the file system and doing nothing if it
+/// isn't indexed
 ///
 /// Arguments:
 ///
-/// * `path`: The path to the file you want to check the status of.
+/// * `path`: The path to the file or directory to be removed.
 ///
 /// Returns:
 ///
-/// GitFileStatus
-pub fn get_file_status(path: &str) -> GitFileStatus {
-    exec_git(vec!["status", "-s", &path])
-        .map(|output| {
-            let status = std::str::from_utf8(&output.stdout)
-                .map(|s| if s.is_empty() { "  " } else { s })
-                .unwrap()
-                .chars()
-                .collect::<Vec<char>>();
-            GitFileStatus {
-                index_status: status[0],
-                working_tree_status: status[1],
-            }
-        })
-        .expect(format!("Failed to get git status: {}", path).as_str())
+/// A Result<(), GitError>
+pub fn rm_cached(path: &str) -> Result<(), GitError> {
+    exec_git(vec![
+        "rm",
+        "-r",
+        "--cached",
+        "--quiet",
+        "--ignore-unmatch",
+        "--",
+        path,
+    ])
+    .map(|_| ())
 }
 
-/// It runs `git status` and checks if the output contains the string `Changes to be committed:`
+/// It runs `git status --porcelain=v2 -z` once for the whole repository and parses the output
+///
+/// Returns:
+///
+/// A Result of a GitStatusMap of every changed, untracked, ignored or conflicting path
+pub fn get_status() -> Result<GitStatusMap, GitError> {
+    exec_git(vec![
+        "status",
+        "--porcelain=v2",
+        "-z",
+        "--untracked-files=all",
+        "--ignored=matching",
+    ])
+    .map(|output| parse_porcelain_v2(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// It parses NUL separated `git status --porcelain=v2 -z` output into a `GitStatusMap`. Rename and
+/// copy entries are followed by an extra field holding the original path.
+///
+/// Arguments:
+///
+/// * `raw`: The raw output of the status command.
+///
+/// Returns:
+///
+/// A GitStatusMap
+pub fn parse_porcelain_v2(raw: &str) -> GitStatusMap {
+    let mut statuses = GitStatusMap::new();
+    let mut records = raw.split('\0').filter(|r| !r.is_empty());
+    while let Some(record) = records.next() {
+        let (kind, rest) = record.split_at(1);
+        let rest = rest.strip_prefix(' ').unwrap_or(rest);
+        let (xy, sub, path) = match kind {
+            "1" => split_changed_entry(rest, 7),
+            "2" => split_changed_entry(rest, 8),
+            "u" => split_changed_entry(rest, 9),
+            "?" => ("??", "N...", rest),
+            "!" => ("!!", "N...", rest),
+            _ => continue,
+        };
+        let mut status = GitFileStatus::from_xy(xy);
+        if sub.starts_with('S') {
+            status.submodule_state = Some(sub.to_string());
+        }
+        if kind == "2" {
+            status.original_path = records.next().map(PathBuf::from);
+        }
+        statuses.insert(PathBuf::from(path.trim_end_matches('/')), status);
+    }
+    statuses
+}
+
+/// It splits the body of a changed, renamed or unmerged entry into its `XY`, submodule and path
+/// fields. The path is always the last field and may itself contain spaces.
+///
+/// Arguments:
+///
+/// * `rest`: The entry without its leading type character.
+/// * `fields_before_path`: The number of space separated fields preceding the path.
+///
+/// Returns:
+///
+/// A tuple of the `XY` field, the submodule field and the path
+fn split_changed_entry(rest: &str, fields_before_path: usize) -> (&str, &str, &str) {
+    let fields = rest
+        .splitn(fields_before_path + 1, ' ')
+        .collect::<Vec<&str>>();
+    match fields.len() {
+        n if n == fields_before_path + 1 => (fields[0], fields[1], fields[n - 1]),
+        _ => panic!("Failed to parse git status entry '{rest}'"),
+    }
+}
+
+/// It checks whether any entry in the repository status has staged changes
+///
+/// Returns:
+///
+/// A Result of a boolean value.
+pub fn is_any_file_staged() -> Result<bool, GitError> {
+    Ok(get_status()?.values().any(GitFileStatus::is_staged))
+}
+
+/// It checks whether any entry in the repository status is unmerged, i.e. `AA`, `AU`, `DD`, `DU`,
+/// `UA`, `UD`, or `UU`
+///
+/// Returns:
+///
+/// A Result of a boolean value.
+pub fn is_any_file_conflicting() -> Result<bool, GitError> {
+    Ok(get_status()?.values().any(GitFileStatus::is_conflicting))
+}
+
+/// It lists the paths of all conflicting files, sorted
+///
+/// Returns:
+///
+/// A Result of a vector of Strings
+pub fn get_conflicting_files() -> Result<Vec<String>, GitError> {
+    let mut paths = get_status()?
+        .into_iter()
+        .filter(|(_, status)| status.is_conflicting())
+        .map(|(path, _)| path.display().to_string())
+        .collect::<Vec<String>>();
+    paths.sort();
+    Ok(paths)
+}
+
+/// It resolves a conflicting file by keeping one side of the conflict. If that side deleted the file,
+/// the file is removed instead
+///
+/// Arguments:
+///
+/// * `path`: The path of the conflicting file.
+/// * `side`: The version of the file to keep.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn take_side(path: &str, side: ConflictSide) -> Result<(), GitError> {
+    let flag = match side {
+        ConflictSide::Ours => "--ours",
+        ConflictSide::Theirs => "--theirs",
+    };
+    if exec_git(vec!["checkout", flag, "--", path]).is_err() {
+        exec_git(vec!["rm", "--quiet", "--", path])?;
+    }
+    mark_resolved(path)
+}
+
+/// It rewrites the conflict markers of a file in the `diff3` style, which also shows the common
+/// ancestor between the two sides
+///
+/// Arguments:
+///
+/// * `path`: The path of the conflicting file.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn restore_conflict_markers(path: &str) -> Result<(), GitError> {
+    exec_git(vec!["checkout", "--conflict=diff3", "--", path]).map(|_| ())
+}
+
+/// It shows the differences between two files, even outside of the repository. Since such a diff
+/// exits with code 1 whenever the files differ, only other failures are errors
+///
+/// Arguments:
+///
+/// * `path`: The path of the first file.
+/// * `other`: The path of the second file.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn show_diff(path: &str, other: &str) -> Result<(), GitError> {
+    match exec_git_with_logs(vec!["diff", "--no-index", "--", path, other]) {
+        Err(GitError::Failed { code: Some(1), .. }) => Ok(()),
+        result => result,
+    }
+}
+
+/// It returns the merge tool configured through `merge.tool`, if any
+///
+/// Returns:
+///
+/// An Option<String>
+pub fn get_merge_tool() -> Option<String> {
+    read_git(vec!["config", "merge.tool"])
+}
+
+/// It opens the configured merge tool for a conflicting file
+///
+/// Arguments:
+///
+/// * `path`: The path of the conflicting file.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn open_merge_tool(path: &str) -> Result<(), GitError> {
+    exec_git_with_logs(vec!["mergetool", "--no-prompt", "--", path])
+}
+
+/// It marks a file as resolved by staging it, or by removing it from the index if it no longer exists
+///
+/// Arguments:
+///
+/// * `path`: The path of the resolved file.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn mark_resolved(path: &str) -> Result<(), GitError> {
+    if std::path::Path::new(path).exists() {
+        exec_git(vec!["add", "--", path])
+    } else {
+        exec_git(vec![
+            "rm",
+            "--quiet",
+            "--cached",
+            "--ignore-unmatch",
+            "--",
+            path,
+        ])
+    }
+    .map(|_| ())
+}
+
+/// It checks whether a path inside the `.git` directory exists, e.g. `MERGE_HEAD`
+///
+/// Arguments:
+///
+/// * `name`: The path relative to the `.git` directory.
 ///
 /// Returns:
 ///
 /// A boolean value.
-pub fn is_any_file_staged() -> bool {
-    exec_git(vec!["status"])
-        .map(|output| {
-            std::str::from_utf8(&output.stdout)
-                .unwrap_or("")
-                .contains("Changes to be committed:")
-        })
-        .expect("Failed to determine if files have been staged")
+fn git_path_exists(name: &str) -> bool {
+    read_git(vec!["rev-parse", "--git-path", name])
+        .map(|p| std::path::Path::new(&p).exists())
+        .unwrap_or(false)
 }
 
-/// It runs `git status -s` and checks if any of the lines start with `AA`, `AU`, `DD`, `DU`, `UA`,
-/// `UD`, or `UU`
+/// It returns the absolute path of a path inside the `.git` directory, which is where configman keeps
+/// the data that must neither be cleaned nor committed
+///
+/// Arguments:
+///
+/// * `name`: The path relative to the `.git` directory.
 ///
 /// Returns:
 ///
-/// A boolean value
-pub fn is_any_file_conflicting() -> bool {
-    exec_git(vec!["status", "-s"])
-        .map(|output| {
-            std::str::from_utf8(&output.stdout)
-                .unwrap_or("")
-                .lines()
-                .any(|l| {
-                    vec!["AA", "AU", "DD", "DU", "UA", "UD", "UU"]
-                        .binary_search(&&l[..2])
-                        .is_ok()
-                })
-            // UU AA AU UA DU UD DD
-        })
-        .expect("Failed to determine if files are conflicting with remote repo")
+/// A PathBuf
+pub fn get_git_path(name: &str) -> PathBuf {
+    let path = read_git(vec!["rev-parse", "--git-path", name])
+        .expect("Failed to find the git directory. Try initializing first.");
+    get_working_dir().join(path)
+}
+
+/// It checks whether a merge or rebase has been interrupted and waits to be concluded
+///
+/// Returns:
+///
+/// A boolean value.
+pub fn is_merge_in_progress() -> bool {
+    git_path_exists("MERGE_HEAD") || is_rebase_in_progress()
 }
 
-/// It commits all staged files with the given message
+/// It checks whether a rebase has been interrupted and waits to be continued
+///
+/// Returns:
+///
+/// A boolean value.
+fn is_rebase_in_progress() -> bool {
+    git_path_exists("rebase-merge") || git_path_exists("rebase-apply")
+}
+
+/// It concludes an interrupted merge or rebase once all conflicts have been resolved. A rebase may
+/// stop again on the conflicts of a later commit.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn continue_merge() -> Result<(), GitError> {
+    if git_path_exists("MERGE_HEAD") {
+        exec_git_with_logs(vec!["commit", "--no-edit"])
+    } else if is_rebase_in_progress() {
+        exec_git_with_logs(vec!["-c", "core.editor=true", "rebase", "--continue"])
+    } else {
+        Ok(())
+    }
+}
+
+/// It commits all staged files with the given message. The files are staged beforehand with
+/// `stage_files`, after being checked for secrets.
 ///
 /// Arguments:
 ///
 /// * `message`: &str
-pub fn commit_staged_files(message: &str) {
-    stage_files();
-    if is_any_file_staged() {
-        exec_git_with_logs(vec!["commit", "-m", &message])
-            .expect(format!("Failed to commit: {}", &message).as_str());
+///
+/// Returns:
+///
+/// A Result of a boolean value. whether a commit was made
+pub fn commit_staged_files(message: &str) -> Result<bool, GitError> {
+    if is_any_file_staged()? {
+        exec_git_with_logs(vec!["commit", "-m", &message]).map(|_| true)
     } else {
-        println!("There are no staged files. Commit has been aborted.");
+        warn!("There are no staged files. Commit has been aborted.");
+        Ok(false)
+    }
+}
+
+/// It stages the given files, including deletions, and commits only those files with the given
+/// message, leaving any other staged changes in the index. Files staged as renamed or copied are
+/// committed along with their original paths, so that a rename isn't split.
+///
+/// Arguments:
+///
+/// * `message`: &str
+/// * `paths`: The paths of the files to commit.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn commit_files(message: &str, paths: &[String]) -> Result<(), GitError> {
+    if paths.is_empty() {
+        warn!("There are no selected files. Commit has been aborted.");
+        return Ok(());
+    }
+    stage_files(paths)?;
+    let statuses = get_status()?;
+    let mut committed = paths.to_vec();
+    for path in paths {
+        let original = statuses
+            .get(&PathBuf::from(path))
+            .and_then(|status| status.original_path.as_ref())
+            .map(|p| p.display().to_string());
+        if let Some(original) = original.filter(|o| !committed.contains(o)) {
+            committed.push(original);
+        }
+    }
+    let mut arg = vec!["commit", "-m", message, "--only", "--"];
+    arg.extend(committed.iter().map(|s| s.as_str()));
+    exec_git_with_logs(arg)
+}
+
+/// It lists the files committed at `HEAD`, which is empty before the first commit
+///
+/// Returns:
+///
+/// A HashSet of the absolute PathBufs
+pub fn get_committed_files() -> HashSet<PathBuf> {
+    let wdir = get_working_dir();
+    read_git(vec!["ls-tree", "-r", "-z", "--name-only", "HEAD"])
+        .map(|out| {
+            out.split('\0')
+                .filter(|p| !p.is_empty())
+                .map(|p| wdir.join(p))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// It returns the commit `HEAD` points at, if there is one
+///
+/// Returns:
+///
+/// An Option<String>
+pub fn get_head() -> Option<String> {
+    read_git(vec!["rev-parse", "--verify", "--quiet", "HEAD"])
+}
+
+/// It moves the current branch back to a commit, updating the files that differ between the commits
+/// and refusing if that would discard local changes to them
+///
+/// Arguments:
+///
+/// * `commit`: The commit to move back to.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn reset_head(commit: &str) -> Result<(), GitError> {
+    exec_git_with_logs(vec!["reset", "--keep", commit])
+}
+
+/// It resets the index entries of the given paths to what `HEAD` has, leaving the files alone
+///
+/// Arguments:
+///
+/// * `paths`: The paths to reset.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn reset_index(paths: &[String]) -> Result<(), GitError> {
+    let mut arg = vec!["reset", "--quiet", "--"];
+    arg.extend(paths.iter().map(|s| s.as_str()));
+    exec_git(arg).map(|_| ())
+}
+
+/// It returns the name of the currently checked out branch, if `HEAD` is not detached
+///
+/// Returns:
+///
+/// An Option<String>
+pub fn get_current_branch() -> Option<String> {
+    read_git(vec!["symbolic-ref", "--quiet", "--short", "HEAD"])
+}
+
+/// It looks up the remote and branch the current branch is configured to track
+///
+/// Returns:
+///
+/// An Option of a tuple of the remote name and the remote branch name
+pub fn get_upstream() -> Option<(String, String)> {
+    let branch = get_current_branch()?;
+    let remote = read_git(vec!["config", &format!("branch.{branch}.remote")])?;
+    let merge = read_git(vec!["config", &format!("branch.{branch}.merge")])?;
+    let merge = merge.strip_prefix("refs/heads/").unwrap_or(&merge);
+    Some((remote, merge.to_string()))
+}
+
+/// It asks git which branch the remote's `HEAD` points at, e.g. `main` for `origin/HEAD -> origin/main`
+///
+/// Arguments:
+///
+/// * `remote`: The name of the remote.
+///
+/// Returns:
+///
+/// An Option<String>
+pub fn get_remote_default_branch(remote: &str) -> Option<String> {
+    let head = read_git(vec![
+        "symbolic-ref",
+        "--quiet",
+        &format!("refs/remotes/{remote}/HEAD"),
+    ])
+    .or_else(|| {
+        read_git(vec!["ls-remote", "--symref", remote, "HEAD"]).and_then(|out| {
+            out.lines()
+                .find_map(|l| l.strip_prefix("ref: "))
+                .and_then(|l| l.split_whitespace().next())
+                .map(|r| r.replace("refs/heads/", &format!("refs/remotes/{remote}/")))
+        })
+    })?;
+    head.strip_prefix(&format!("refs/remotes/{remote}/"))
+        .map(|b| b.to_string())
+}
+
+/// It lists the branches of a remote that have been fetched, sorted by name
+///
+/// Arguments:
+///
+/// * `remote`: The name of the remote.
+///
+/// Returns:
+///
+/// A vector of Strings
+pub fn get_remote_branches(remote: &str) -> Vec<String> {
+    read_git(vec![
+        "for-each-ref",
+        "--format=%(refname:strip=3)",
+        &format!("refs/remotes/{remote}"),
+    ])
+    .map(|out| {
+        out.lines()
+            .filter(|b| *b != "HEAD")
+            .map(String::from)
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+/// It decides which remote to use, preferring the configured one, then the remote the current branch
+/// tracks and finally `origin`
+///
+/// Arguments:
+///
+/// * `configured`: The remote named in the options or config file, if any.
+///
+/// Returns:
+///
+/// A String
+pub fn resolve_remote(configured: Option<&str>) -> String {
+    configured
+        .map(String::from)
+        .or_else(|| get_upstream().map(|(remote, _)| remote))
+        .unwrap_or_else(|| "origin".to_string())
+}
+
+/// It decides which remote and branch to fetch, preferring explicit options, then the upstream of
+/// the current branch, then the remote's default branch and finally the current branch name
+///
+/// Arguments:
+///
+/// * `options`: The fetch options.
+///
+/// Returns:
+///
+/// A tuple of the remote name and the remote branch name
+pub fn resolve_fetch_target(options: &FetchOptions) -> (String, String) {
+    let upstream = get_upstream();
+    let remote = resolve_remote(options.remote.as_deref());
+    let branch = options
+        .branch
+        .clone()
+        .or_else(|| upstream.filter(|(r, _)| r == &remote).map(|(_, b)| b))
+        .or_else(|| get_remote_default_branch(&remote))
+        .or_else(get_current_branch)
+        .expect("Failed to determine which remote branch to fetch");
+    (remote, branch)
+}
+
+/// It fetches the remote branch without applying it and lists the tracked files that applying it with
+/// the chosen strategy would change. A hard reset changes every file that differs from the fetched
+/// branch, while a rebase or merge brings in the changes made on it since the branches diverged.
+///
+/// Arguments:
+///
+/// * `options`: The fetch options.
+///
+/// Returns:
+///
+/// A Result of a vector of the status letter, e.g. `M` or `D`, and the path of every incoming change
+pub fn get_incoming_changes(options: &FetchOptions) -> Result<Vec<(char, String)>, GitError> {
+    let (remote, branch) = resolve_fetch_target(options);
+    let target = format!("{remote}/{branch}");
+    exec_git_with_logs(vec!["fetch", &remote, &branch])?;
+    let head = get_head();
+    let changes = match head {
+        None => exec_git(vec!["ls-tree", "-r", "-z", "--name-only", &target])?,
+        Some(_) if options.strategy == FetchStrategy::Reset => exec_git(vec![
+            "diff",
+            "--name-status",
+            "--no-renames",
+            "-z",
+            "HEAD",
+            &target,
+        ])?,
+        Some(_) => exec_git(vec![
+            "diff",
+            "--name-status",
+            "--no-renames",
+            "-z",
+            &format!("HEAD...{target}"),
+        ])?,
+    };
+    let fields = String::from_utf8_lossy(&changes.stdout)
+        .split_terminator('\0')
+        .map(String::from)
+        .collect::<Vec<String>>();
+    if head.is_none() {
+        return Ok(fields.into_iter().map(|path| ('A', path)).collect());
     }
+    Ok(fields
+        .chunks_exact(2)
+        .map(|change| (change[0].chars().next().unwrap_or('M'), change[1].clone()))
+        .collect())
 }
 
-/// It fetches the latest commits from the remote repository and overwrites the local repository with
-/// them
-pub fn fetch() {
-    exec_git_with_logs(vec!["fetch", "origin"]).expect("Failed to fetch from remote git repo");
-    exec_git_with_logs(vec!["reset", "--hard", "origin/master"])
-        .expect("Overwrite local with remote commits");
+/// It checks whether any tracked file has staged or unstaged changes that a hard reset would discard
+///
+/// Returns:
+///
+/// A Result of a boolean value.
+pub fn has_uncommitted_changes() -> Result<bool, GitError> {
+    Ok(!get_uncommitted_files()?.is_empty())
 }
 
-/// It executes the git push command
-pub fn push() {
-    exec_git_with_logs(vec!["push"]).expect("Failed to push to remote git repo");
+/// It lists the tracked files with staged or unstaged changes, which a hard reset would discard
+///
+/// Returns:
+///
+/// A Result of a vector of the absolute PathBufs
+fn get_uncommitted_files() -> Result<Vec<PathBuf>, GitError> {
+    let wdir = get_working_dir();
+    Ok(get_status()?
+        .into_iter()
+        .filter(|(_, status)| !status.is_ignored() && !status.is_untracked())
+        .map(|(path, _)| wdir.join(path))
+        .collect())
+}
+
+/// It fetches the latest commits from the remote repository and applies them to the local branch
+/// using the chosen strategy. A hard reset is refused when there are uncommitted changes, unless they
+/// may be discarded, or local commits the remote branch lacks, unless forced, and the changed files are backed up before they're
+/// overwritten. A rebase or merge that stops at conflicts still counts as applied, leaving the
+/// conflicts to be resolved.
+///
+/// Arguments:
+///
+/// * `options`: The fetch options.
+///
+/// Returns:
+///
+/// A Result of a boolean value. whether the remote commits were applied
+pub fn fetch(options: &FetchOptions) -> Result<bool, GitError> {
+    let (remote, branch) = resolve_fetch_target(options);
+    let target = format!("{remote}/{branch}");
+    if options.strategy == FetchStrategy::Reset
+        && !(options.force || options.discard_changes)
+        && has_uncommitted_changes()?
+    {
+        warn!("Refusing to overwrite uncommitted changes with {target}. Commit them or force the fetch.");
+        return Ok(false);
+    }
+    exec_git_with_logs(vec!["fetch", &remote, &branch])?;
+    if options.strategy == FetchStrategy::Reset && !options.force {
+        let unpushed = count_commits_missing_from(&target)?;
+        if unpushed > 0 {
+            warn!("Refusing to discard {unpushed} local commit(s) missing from {target}. Push them, fetch with --rebase or --merge, or force the fetch.");
+            return Ok(false);
+        }
+    }
+    if let Some(head) = get_head() {
+        record(JournalEntry::Head(head));
+    }
+    let applied = match options.strategy {
+        FetchStrategy::Reset => {
+            back_up("fetch", &get_uncommitted_files()?);
+            exec_git_with_logs(vec!["reset", "--hard", &target])
+        }
+        FetchStrategy::Rebase => exec_git_with_logs(vec!["rebase", &target]),
+        FetchStrategy::Merge => exec_git_with_logs(vec!["merge", &target]),
+    };
+    match applied {
+        Err(e @ GitError::Failed { .. }) => match is_any_file_conflicting()? {
+            true => Ok(true),
+            false => Err(e),
+        },
+        applied => applied.map(|_| true),
+    }
+}
+
+/// It counts the commits of the current branch that a branch lacks, none if there are no commits yet
+///
+/// Arguments:
+///
+/// * `target`: The branch to compare with, e.g. `origin/main`.
+///
+/// Returns:
+///
+/// A Result of the number of commits
+fn count_commits_missing_from(target: &str) -> Result<usize, GitError> {
+    if get_head().is_none() {
+        return Ok(0);
+    }
+    let count = exec_git(vec!["rev-list", "--count", &format!("{target}..HEAD")])?;
+    Ok(String::from_utf8_lossy(&count.stdout)
+        .trim()
+        .parse()
+        .unwrap_or(0))
+}
+
+/// It lists the files changed by the commits that a push to the remote would publish, or every tracked
+/// file if the current branch doesn't track a branch on that remote yet
+///
+/// Arguments:
+///
+/// * `remote`: The name of the remote to push to.
+///
+/// Returns:
+///
+/// A vector of PathBufs
+pub fn get_outgoing_files(remote: &str) -> Vec<PathBuf> {
+    let files = match get_upstream().filter(|(r, _)| r == remote) {
+        Some((r, branch)) => read_git(vec!["diff", "--name-only", &format!("{r}/{branch}...HEAD")]),
+        None => read_git(vec!["ls-files"]),
+    };
+    files
+        .map(|out| out.lines().map(PathBuf::from).collect())
+        .unwrap_or_default()
+}
+
+/// It executes the git push command, setting the upstream of the current branch on the first push
+/// to the remote
+///
+/// Arguments:
+///
+/// * `remote`: The name of the remote to push to.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn push(remote: &str) -> Result<(), GitError> {
+    exec_git_with_logs(push_args(remote, false))
 }
 
 /// It executes the `git push` command with the argument `--force`
-pub fn force_push() {
-    exec_git_with_logs(vec!["push", "--force"]).expect("Failed to force push to remote git repo");
+///
+/// Arguments:
+///
+/// * `remote`: The name of the remote to push to.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn force_push(remote: &str) -> Result<(), GitError> {
+    exec_git_with_logs(push_args(remote, true))
+}
+
+/// It builds the arguments of a push to the remote, adding `--set-upstream` if the current branch
+/// doesn't track a branch on that remote yet
+///
+/// Arguments:
+///
+/// * `remote`: The name of the remote to push to.
+/// * `force`: Whether to force the push.
+///
+/// Returns:
+///
+/// A Vec<&str>
+fn push_args(remote: &str, force: bool) -> Vec<&str> {
+    let mut arg = vec!["push"];
+    if force {
+        arg.push("--force");
+    }
+    if get_upstream().is_none_or(|(r, _)| r != remote) {
+        arg.extend(["--set-upstream", remote, "HEAD"]);
+    }
+    arg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_changed_entry_keeps_spaces_in_the_path() {
+        let entry = ".M N... 100644 100644 100644 abc123 abc123 my file.txt";
+        assert_eq!(split_changed_entry(entry, 7), (".M", "N...", "my file.txt"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Failed to parse git status entry")]
+    fn split_changed_entry_rejects_truncated_entries() {
+        split_changed_entry(".M N... 100644", 7);
+    }
+
+    #[test]
+    fn parse_porcelain_v2_reads_changed_untracked_and_ignored_entries() {
+        let raw = "1 .M N... 100644 100644 100644 abc abc config.cmf\0\
+                   1 A. N... 000000 100644 100644 000 def nvim/init.lua\0\
+                   ? notes.txt\0\
+                   ! target/\0";
+        let statuses = parse_porcelain_v2(raw);
+        assert_eq!(statuses.len(), 4);
+        let modified = &statuses[&PathBuf::from("config.cmf")];
+        assert_eq!(
+            (modified.index_status, modified.working_tree_status),
+            (' ', 'M')
+        );
+        assert!(!modified.is_staged());
+        assert!(statuses[&PathBuf::from("nvim/init.lua")].is_staged());
+        assert!(statuses[&PathBuf::from("notes.txt")].is_untracked());
+        assert!(statuses[&PathBuf::from("target")].is_ignored());
+    }
+
+    #[test]
+    fn parse_porcelain_v2_reads_the_original_path_of_renames() {
+        let raw = "2 R. N... 100644 100644 100644 abc abc R100 new name.conf\0old name.conf\0\
+                   1 .M N... 100644 100644 100644 abc abc other.conf\0";
+        let statuses = parse_porcelain_v2(raw);
+        assert_eq!(statuses.len(), 2);
+        let renamed = &statuses[&PathBuf::from("new name.conf")];
+        assert_eq!(renamed.index_status, 'R');
+        assert_eq!(renamed.original_path, Some(PathBuf::from("old name.conf")));
+        assert!(statuses.contains_key(&PathBuf::from("other.conf")));
+    }
+
+    #[test]
+    fn parse_porcelain_v2_reads_unmerged_entries() {
+        let raw = "u UU N... 100644 100644 100644 100644 abc def ghi conflicted file\0\
+                   u DU N... 100644 000000 100644 100644 abc 000 ghi deleted\0";
+        let statuses = parse_porcelain_v2(raw);
+        let both = &statuses[&PathBuf::from("conflicted file")];
+        assert!(both.is_conflicting());
+        assert!(!both.is_staged());
+        assert!(statuses[&PathBuf::from("deleted")].is_conflicting());
+    }
+
+    #[test]
+    fn parse_porcelain_v2_keeps_the_submodule_state() {
+        let raw = "1 .M SC.. 160000 160000 160000 abc abc vendor/lib\0";
+        let statuses = parse_porcelain_v2(raw);
+        assert_eq!(
+            statuses[&PathBuf::from("vendor/lib")].submodule_state,
+            Some("SC..".to_string())
+        );
+    }
 }
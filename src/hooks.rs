@@ -0,0 +1,140 @@
+use log::{error, info};
+use std::fmt;
+use std::process::Command;
+
+use crate::config::{Config, Mapping};
+use crate::fs::get_working_dir;
+use crate::process::resolve_destination;
+
+/// `Hook` is an enum of the points around actions at which commands can be run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    PreRefresh,
+    PostRefresh,
+    PostDeploy,
+    PreCommit,
+    PrePush,
+}
+
+/// Implementing the `Display` trait for the `Hook` enum.
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.key())
+    }
+}
+
+impl Hook {
+    /// It returns the setting key the hook is declared with in the config file
+    ///
+    /// Returns:
+    ///
+    /// A &str
+    pub const fn key(&self) -> &'static str {
+        match self {
+            Hook::PreRefresh => "pre-refresh",
+            Hook::PostRefresh => "post-refresh",
+            Hook::PostDeploy => "post-deploy",
+            Hook::PreCommit => "pre-commit",
+            Hook::PrePush => "pre-push",
+        }
+    }
+}
+
+/// It checks whether the hook is declared anywhere in the config, globally or on a mapping
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `hook`: The hook.
+///
+/// Returns:
+///
+/// A boolean value.
+pub fn has_hooks(config: &Config, hook: Hook) -> bool {
+    !config.settings_named(hook.key()).is_empty()
+        || config
+            .mappings
+            .iter()
+            .any(|m| !m.settings_named(hook.key()).is_empty())
+}
+
+/// It runs the global commands of a hook with every affected file, then the commands declared on
+/// mappings with the affected files that carry them. Commands declared on several mappings run once.
+/// It stops at the first failing command.
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `hook`: The hook to run.
+/// * `affected`: The found mappings affected by the action.
+///
+/// Returns:
+///
+/// A boolean value. whether every command succeeded
+pub fn run_hooks(config: &Config, hook: Hook, affected: &[Mapping]) -> bool {
+    let global = config
+        .settings_named(hook.key())
+        .into_iter()
+        .map(|command| (command, affected.iter().collect::<Vec<&Mapping>>()))
+        .collect::<Vec<(&str, Vec<&Mapping>)>>();
+    let mut scoped: Vec<(&str, Vec<&Mapping>)> = Vec::new();
+    for mapping in affected {
+        for command in mapping.settings_named(hook.key()) {
+            match scoped.iter_mut().find(|(c, _)| *c == command) {
+                Some((_, mappings)) => mappings.push(mapping),
+                None => scoped.push((command, vec![mapping])),
+            }
+        }
+    }
+    global
+        .iter()
+        .chain(scoped.iter())
+        .all(|(command, mappings)| run_hook_command(hook, command, mappings))
+}
+
+/// It runs a hook command through `sh -c` in the working directory. The command finds the name of
+/// the hook in `$CONFIGMAN_HOOK`, and the affected files, one per line, in `$CONFIGMAN_SOURCES` and
+/// `$CONFIGMAN_DESTINATIONS`.
+///
+/// Arguments:
+///
+/// * `hook`: The hook being run.
+/// * `command`: The command to run.
+/// * `mappings`: The affected files.
+///
+/// Returns:
+///
+/// A boolean value. whether the command succeeded
+fn run_hook_command(hook: Hook, command: &str, mappings: &[&Mapping]) -> bool {
+    let sources = mappings
+        .iter()
+        .filter(|m| !m.source.is_empty())
+        .map(|m| m.source.as_str())
+        .collect::<Vec<&str>>();
+    let destinations = mappings
+        .iter()
+        .map(|m| resolve_destination(&m.destination).display().to_string())
+        .collect::<Vec<String>>();
+    info!("Running {hook} hook: {command}");
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .current_dir(get_working_dir())
+        .env("CONFIGMAN_HOOK", hook.key())
+        .env("CONFIGMAN_WORKING_DIR", get_working_dir())
+        .env("CONFIGMAN_SOURCES", sources.join("\n"))
+        .env("CONFIGMAN_DESTINATIONS", destinations.join("\n"))
+        .env("CONFIGMAN_FILE_COUNT", mappings.len().to_string())
+        .status();
+    match status {
+        Ok(status) if status.success() => true,
+        Ok(status) => {
+            error!("The {hook} hook `{command}` failed with {status}");
+            false
+        }
+        Err(e) => {
+            error!("Failed to run the {hook} hook `{command}`: {:?}", e);
+            false
+        }
+    }
+}
@@ -0,0 +1,335 @@
+use log::{info, warn};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::symlink;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::fs::{move_dir, remove_from_fs};
+use crate::git::{get_git_path, reset_head, reset_index, GitError};
+
+/// The directory inside the git directory the journal of the last run is kept in.
+const JOURNAL_DIR: &str = "configman/journal";
+
+/// The file the entries of the journal are appended to, one per line.
+const ENTRIES_FILE: &str = "entries";
+
+/// The directory the removed files are moved to, so that they can be put back.
+const KEPT_DIR: &str = "kept";
+
+/// The directory of the journal being written, while a journaled operation runs.
+static JOURNAL: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// `JournalEntry` is an enum of the changes recorded in the journal, written as tab separated lines
+/// whose fields are escaped, see `escape_field`.
+///
+/// * `Run`: The operation the journal belongs to, always the first entry.
+/// * `Linked`: A link was created at the path.
+/// * `Removed`: The path was removed, and moved to the kept file of the given name.
+/// * `Indexed`: The path was added to the index.
+/// * `Unindexed`: The path was removed from the index.
+/// * `Head`: The commit `HEAD` pointed at before it was moved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JournalEntry {
+    Run(String),
+    Linked(PathBuf),
+    Removed(PathBuf, String),
+    Indexed(String),
+    Unindexed(String),
+    Head(String),
+}
+
+/// It's implementing the `Display` trait for the `JournalEntry` enum, giving the line it's written as.
+impl fmt::Display for JournalEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JournalEntry::Run(operation) => write!(f, "run\t{}", escape_field(operation)),
+            JournalEntry::Linked(path) => {
+                write!(f, "link\t{}", escape_field(&path.display().to_string()))
+            }
+            JournalEntry::Removed(path, kept) => write!(
+                f,
+                "remove\t{}\t{}",
+                escape_field(&path.display().to_string()),
+                escape_field(kept)
+            ),
+            JournalEntry::Indexed(path) => write!(f, "index\t{}", escape_field(path)),
+            JournalEntry::Unindexed(path) => write!(f, "unindex\t{}", escape_field(path)),
+            JournalEntry::Head(commit) => write!(f, "head\t{}", escape_field(commit)),
+        }
+    }
+}
+
+impl JournalEntry {
+    /// It parses a line of the journal
+    ///
+    /// Arguments:
+    ///
+    /// * `line`: The line.
+    ///
+    /// Returns:
+    ///
+    /// An Option of the JournalEntry
+    fn parse(line: &str) -> Option<JournalEntry> {
+        let fields = line
+            .split('\t')
+            .map(unescape_field)
+            .collect::<Option<Vec<String>>>()?;
+        let fields = fields.iter().map(String::as_str).collect::<Vec<&str>>();
+        match fields[..] {
+            ["run", operation] => Some(JournalEntry::Run(operation.to_string())),
+            ["link", path] => Some(JournalEntry::Linked(PathBuf::from(path))),
+            ["remove", path, kept] => {
+                Some(JournalEntry::Removed(PathBuf::from(path), kept.to_string()))
+            }
+            ["index", path] => Some(JournalEntry::Indexed(path.to_string())),
+            ["unindex", path] => Some(JournalEntry::Unindexed(path.to_string())),
+            ["head", commit] => Some(JournalEntry::Head(commit.to_string())),
+            _ => None,
+        }
+    }
+}
+
+/// It escapes the backslashes, tabs and newlines of a field, so that paths containing them survive
+/// being written as a tab separated line
+///
+/// Arguments:
+///
+/// * `field`: The field.
+///
+/// Returns:
+///
+/// A String
+fn escape_field(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// It reverses `escape_field`
+///
+/// Arguments:
+///
+/// * `field`: The escaped field.
+///
+/// Returns:
+///
+/// An Option<String>, which is None if the field contains an unknown escape
+fn unescape_field(field: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => unescaped.push('\\'),
+            't' => unescaped.push('\t'),
+            'n' => unescaped.push('\n'),
+            'r' => unescaped.push('\r'),
+            _ => return None,
+        }
+    }
+    Some(unescaped)
+}
+
+/// It runs an operation while recording its changes in a new journal, replacing the journal of the
+/// previous run
+///
+/// Arguments:
+///
+/// * `operation`: The name of the operation, e.g. `refresh`.
+/// * `run`: The operation.
+///
+/// Returns:
+///
+/// The result of the operation
+pub fn journaled<T>(operation: &str, run: impl FnOnce() -> T) -> T {
+    let dir = get_git_path(JOURNAL_DIR);
+    if dir.exists() {
+        fs::remove_dir_all(&dir).unwrap_or_else(|_| panic!("Failed to remove {:?}", &dir));
+    }
+    fs::create_dir_all(dir.join(KEPT_DIR))
+        .unwrap_or_else(|_| panic!("Failed to create {:?}", &dir));
+    *JOURNAL.lock().unwrap() = Some(dir);
+    record(JournalEntry::Run(operation.to_string()));
+    let result = run();
+    *JOURNAL.lock().unwrap() = None;
+    result
+}
+
+/// It appends an entry to the journal, if an operation is being journaled. Links created inside the
+/// journal itself, while keeping removed files, are left out.
+///
+/// Arguments:
+///
+/// * `entry`: The entry.
+pub fn record(entry: JournalEntry) {
+    let journal = JOURNAL.lock().unwrap();
+    let Some(dir) = journal.as_ref() else {
+        return;
+    };
+    if matches!(&entry, JournalEntry::Linked(path) if path.starts_with(dir)) {
+        return;
+    }
+    let path = dir.join(ENTRIES_FILE);
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .unwrap_or_else(|_| panic!("Failed to open {:?}", &path));
+    writeln!(file, "{entry}").unwrap_or_else(|_| panic!("Failed to write to {:?}", &path));
+}
+
+/// It moves a file, directory or symbolic link, copying it over and removing the original when it
+/// can't simply be renamed, e.g. across file systems
+///
+/// Arguments:
+///
+/// * `from`: The path to move.
+/// * `to`: The new path.
+fn move_entry(from: &PathBuf, to: &PathBuf) {
+    to.parent().map(fs::create_dir_all);
+    if fs::rename(from, to).is_ok() {
+        return;
+    }
+    if from.is_symlink() {
+        let target = fs::read_link(from)
+            .unwrap_or_else(|_| panic!("Failed to read the target of {:?}", &from));
+        symlink(&target, to).unwrap_or_else(|_| panic!("Failed to move {:?} to {:?}", &from, &to));
+        fs::remove_file(from).unwrap_or_else(|_| panic!("Failed to remove {:?}", &from));
+    } else if from.is_dir() {
+        move_dir(from, to);
+    } else {
+        fs::copy(from, to).unwrap_or_else(|_| panic!("Failed to move {:?} to {:?}", &from, &to));
+        fs::remove_file(from).unwrap_or_else(|_| panic!("Failed to remove {:?}", &from));
+    }
+}
+
+/// It moves a path that is about to be removed into the journal instead, if an operation is being
+/// journaled. Renaming keeps hard links intact, so undoing puts back the very same file.
+///
+/// Arguments:
+///
+/// * `path`: The path to remove.
+///
+/// Returns:
+///
+/// A boolean value. whether the path has been moved into the journal
+pub fn keep_removed(path: &PathBuf) -> bool {
+    let Some(dir) = JOURNAL.lock().unwrap().clone() else {
+        return false;
+    };
+    if path.symlink_metadata().is_err() {
+        return false;
+    }
+    let kept_dir = dir.join(KEPT_DIR);
+    let kept = kept_dir
+        .read_dir()
+        .map(|entries| entries.count())
+        .unwrap_or(0)
+        .to_string();
+    move_entry(path, &kept_dir.join(&kept));
+    record(JournalEntry::Removed(path.clone(), kept));
+    true
+}
+
+/// It undoes the most recent journaled operation by replaying its journal in reverse: links are
+/// removed, removed files are put back, `HEAD` is moved back and the touched index entries are reset
+/// to what `HEAD` has. The journal is discarded afterwards.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn undo() -> Result<(), GitError> {
+    let dir = get_git_path(JOURNAL_DIR);
+    let entries = fs::read_to_string(dir.join(ENTRIES_FILE))
+        .map(|content| {
+            content
+                .lines()
+                .filter_map(JournalEntry::parse)
+                .collect::<Vec<JournalEntry>>()
+        })
+        .unwrap_or_default();
+    let Some((JournalEntry::Run(operation), changes)) = entries.split_first() else {
+        warn!("There is nothing to undo.");
+        return Ok(());
+    };
+    let mut index = Vec::new();
+    for change in changes.iter().rev() {
+        match change {
+            JournalEntry::Linked(path) => remove_from_fs(path),
+            JournalEntry::Removed(path, kept) => {
+                remove_from_fs(path);
+                move_entry(&dir.join(KEPT_DIR).join(kept), path);
+            }
+            JournalEntry::Indexed(path) | JournalEntry::Unindexed(path) => index.push(path.clone()),
+            JournalEntry::Head(commit) => reset_head(commit)?,
+            JournalEntry::Run(_) => {}
+        }
+    }
+    if !index.is_empty() {
+        reset_index(&index)?;
+    }
+    fs::remove_dir_all(&dir).unwrap_or_else(|_| panic!("Failed to remove {:?}", &dir));
+    info!("Undid the last {operation}: {} change(s)", changes.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn journal_entries_are_written_as_tab_separated_lines() {
+        assert_eq!(
+            JournalEntry::Run("refresh".into()).to_string(),
+            "run\trefresh"
+        );
+        assert_eq!(
+            JournalEntry::Removed(PathBuf::from("/wd/a"), "0".into()).to_string(),
+            "remove\t/wd/a\t0"
+        );
+        assert_eq!(
+            JournalEntry::Indexed("a\tb\nc\\d".into()).to_string(),
+            "index\ta\\tb\\nc\\\\d"
+        );
+    }
+
+    #[test]
+    fn journal_entries_survive_being_written_and_parsed() {
+        let entries = vec![
+            JournalEntry::Run("pull".into()),
+            JournalEntry::Linked(PathBuf::from("/wd/with\ttab")),
+            JournalEntry::Removed(PathBuf::from("/wd/with\nnewline"), "3".into()),
+            JournalEntry::Indexed("back\\slash".into()),
+            JournalEntry::Unindexed("carriage\rreturn".into()),
+            JournalEntry::Head("0123456789abcdef".into()),
+        ];
+        for entry in entries {
+            let line = entry.to_string();
+            assert!(!line.contains('\n'));
+            assert_eq!(JournalEntry::parse(&line), Some(entry));
+        }
+    }
+
+    #[test]
+    fn journal_entry_parse_rejects_malformed_lines() {
+        assert_eq!(JournalEntry::parse(""), None);
+        assert_eq!(JournalEntry::parse("link"), None);
+        assert_eq!(JournalEntry::parse("link\ta\tb"), None);
+        assert_eq!(JournalEntry::parse("move\ta"), None);
+        assert_eq!(JournalEntry::parse("index\tunknown\\escape"), None);
+        assert_eq!(JournalEntry::parse("index\ttrailing\\"), None);
+    }
+}
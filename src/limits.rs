@@ -0,0 +1,214 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+
+use crate::config::{Config, Mapping};
+use crate::git::get_committed_files;
+use crate::process::resolve_destination;
+
+/// The largest source file indexed unless `@max-file-size` says otherwise.
+const DEFAULT_MAX_FILE_SIZE: &str = "10M";
+
+/// The largest total size of the indexed source files unless `@max-total-size` says otherwise.
+const DEFAULT_MAX_TOTAL_SIZE: &str = "100M";
+
+/// How many bytes at the start of a file are checked for a NUL byte to tell binary files apart.
+const BINARY_CHECK_LEN: usize = 8000;
+
+/// `LimitViolation` is a struct that describes a found file that is left out of the working directory.
+///
+/// Properties:
+///
+/// * `source`: The path of the source file.
+/// * `reason`: Why the file was left out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LimitViolation {
+    pub source: String,
+    pub reason: String,
+}
+
+/// It's implementing the `Display` trait for the `LimitViolation` struct.
+impl fmt::Display for LimitViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.source, self.reason)
+    }
+}
+
+/// It checks the found mappings against the file size, total size and binary file limits. A mapping
+/// setting takes precedence over the global one, except for the total size which is global only.
+/// Files committed already are never left out, although they count towards the total size, and the
+/// files are checked in the order of their sources so that the same files are left out every time.
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `found`: The found mappings.
+///
+/// Returns:
+///
+/// A tuple of the mappings within the limits and the violations
+pub fn enforce_limits(config: &Config, found: Vec<Mapping>) -> (Vec<Mapping>, Vec<LimitViolation>) {
+    let max_total_size = expect_size(
+        config
+            .setting("max-total-size")
+            .unwrap_or(DEFAULT_MAX_TOTAL_SIZE),
+    );
+    let committed = get_committed_files();
+    let mut found = found;
+    found.sort_by(|a, b| a.source.cmp(&b.source));
+    let mut total_size = 0;
+    let mut allowed = Vec::new();
+    let mut violations = Vec::new();
+    for mapping in found {
+        let setting = |key: &str| {
+            mapping
+                .settings_named(key)
+                .last()
+                .copied()
+                .or_else(|| config.setting(key))
+        };
+        let max_file_size = expect_size(setting("max-file-size").unwrap_or(DEFAULT_MAX_FILE_SIZE));
+        let allow_binary = setting("allow-binary") == Some("true");
+        let size = fs::metadata(&mapping.source).map(|m| m.len()).unwrap_or(0);
+        let reason = if committed.contains(&resolve_destination(&mapping.destination)) {
+            None
+        } else if size > max_file_size {
+            Some(format!(
+                "{} exceeds the {} limit",
+                format_size(size),
+                format_size(max_file_size)
+            ))
+        } else if !allow_binary && is_binary(&mapping.source) {
+            Some("binary file".to_string())
+        } else if total_size + size > max_total_size {
+            Some(format!(
+                "exceeds the {} total size limit",
+                format_size(max_total_size)
+            ))
+        } else {
+            None
+        };
+        match reason {
+            Some(reason) => violations.push(LimitViolation {
+                source: mapping.source.clone(),
+                reason,
+            }),
+            None => {
+                total_size += size;
+                allowed.push(mapping);
+            }
+        }
+    }
+    (allowed, violations)
+}
+
+/// It checks whether a file contains a NUL byte near its start
+///
+/// Arguments:
+///
+/// * `path`: The path of the file.
+///
+/// Returns:
+///
+/// A boolean value.
+pub fn is_binary(path: &str) -> bool {
+    let mut buffer = Vec::with_capacity(BINARY_CHECK_LEN);
+    File::open(path)
+        .and_then(|f| f.take(BINARY_CHECK_LEN as u64).read_to_end(&mut buffer))
+        .is_ok_and(|_| buffer.contains(&0))
+}
+
+/// It parses a size given as a number followed by an optional unit of `B`, `K`, `M` or `G`, defaulting
+/// to bytes, e.g. `512`, `64K` or `1G`
+///
+/// Arguments:
+///
+/// * `arg`: The size setting.
+///
+/// Returns:
+///
+/// An Option<u64> of bytes, which is None if the size is invalid or too large
+pub fn parse_size(arg: &str) -> Option<u64> {
+    let arg = arg.trim();
+    let (amount, unit) = arg.split_at(arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len()));
+    let bytes = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        _ => return None,
+    };
+    amount
+        .parse::<u64>()
+        .ok()
+        .and_then(|amount| amount.checked_mul(bytes))
+}
+
+/// It parses a size setting, failing if it isn't valid
+///
+/// Arguments:
+///
+/// * `arg`: The size setting.
+///
+/// Returns:
+///
+/// A u64 of bytes
+fn expect_size(arg: &str) -> u64 {
+    parse_size(arg).unwrap_or_else(|| panic!("{:?} is not a valid size", &arg))
+}
+
+/// It formats a number of bytes with the largest unit that keeps it above one
+///
+/// Arguments:
+///
+/// * `size`: The number of bytes.
+///
+/// Returns:
+///
+/// A String
+fn format_size(size: u64) -> String {
+    match size {
+        s if s >= 1 << 30 => format!("{:.1}G", s as f64 / (1u64 << 30) as f64),
+        s if s >= 1 << 20 => format!("{:.1}M", s as f64 / (1u64 << 20) as f64),
+        s if s >= 1 << 10 => format!("{:.1}K", s as f64 / (1u64 << 10) as f64),
+        s => format!("{s}B"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_size_reads_bytes_by_default() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("512B"), Some(512));
+        assert_eq!(parse_size(" 0 "), Some(0));
+    }
+
+    #[test]
+    fn parse_size_reads_units_in_any_case() {
+        assert_eq!(parse_size("64K"), Some(64 << 10));
+        assert_eq!(parse_size("64kb"), Some(64 << 10));
+        assert_eq!(parse_size("10M"), Some(10 << 20));
+        assert_eq!(parse_size("1g"), Some(1 << 30));
+    }
+
+    #[test]
+    fn parse_size_rejects_invalid_sizes() {
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("M"), None);
+        assert_eq!(parse_size("1.5M"), None);
+        assert_eq!(parse_size("10T"), None);
+        assert_eq!(parse_size("-1"), None);
+        assert_eq!(parse_size("99999999999G"), None);
+        assert_eq!(parse_size("99999999999999999999"), None);
+    }
+
+    #[test]
+    fn format_size_uses_the_largest_unit() {
+        assert_eq!(format_size(512), "512B");
+        assert_eq!(format_size(1536), "1.5K");
+        assert_eq!(format_size(10 << 20), "10.0M");
+    }
+}
@@ -0,0 +1,681 @@
+use capturing_glob::Pattern;
+use log::{error, info, warn};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path};
+use toml_edit::DocumentMut;
+
+use crate::backup::BACKUPS_KEY;
+use crate::config::{
+    considered_mapping, convert_line_to_mapping, convert_line_to_setting, get_config_file,
+    get_config_path, is_toml_config, Mapping, CONFIG_FILE,
+};
+use crate::fs::get_working_dir;
+use crate::git::GitError;
+use crate::limits::parse_size;
+use crate::output::{print_json, OutputFormat};
+use crate::process::{find_mapping_matches, resolve_destination, split_pattern_groups};
+use crate::toml_config::{find_table_lines, migrate_config, parse_toml_config};
+
+/// The settings that only apply to the whole config.
+const GLOBAL_KEYS: [&str; 4] = ["remote", "branch", "max-total-size", BACKUPS_KEY];
+
+/// The settings that can also be declared on a mapping.
+const MAPPING_KEYS: [&str; 12] = [
+    "pre-refresh",
+    "post-refresh",
+    "post-deploy",
+    "pre-commit",
+    "pre-push",
+    "allow-secret",
+    "exclude",
+    "max-file-size",
+    "allow-binary",
+    "preserve-owner",
+    "symlinks",
+    "mode",
+];
+
+/// `ConfigAction` is an enum of the things that can be done with the config file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigAction {
+    Check(OutputFormat),
+    Fmt,
+    Migrate,
+}
+
+/// `Lint` is a struct that describes a problem found in the config file.
+///
+/// Properties:
+///
+/// * `line`: The line number of the problem.
+/// * `error`: Whether the config can't be used as is, rather than merely looking wrong.
+/// * `message`: The description of the problem.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Lint {
+    pub line: usize,
+    pub error: bool,
+    pub message: String,
+}
+
+/// `CheckReport` is a struct that describes the problems found in the config file. It's the JSON
+/// output of `config check`, which still fails when there are errors.
+///
+/// Properties:
+///
+/// * `config_file`: The name of the checked config file.
+/// * `errors`: The number of errors.
+/// * `warnings`: The number of warnings.
+/// * `lints`: The problems, ordered by line.
+#[derive(Debug, Serialize)]
+struct CheckReport {
+    config_file: &'static str,
+    errors: usize,
+    warnings: usize,
+    lints: Vec<Lint>,
+}
+
+/// It's implementing the `Display` trait for the `Lint` struct.
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = if self.error { "error" } else { "warning" };
+        write!(
+            f,
+            "{}:{}: {}: {}",
+            get_config_file(),
+            self.line,
+            severity,
+            self.message
+        )
+    }
+}
+
+/// It performs a config action
+///
+/// Arguments:
+///
+/// * `action`: The config action.
+///
+/// Returns:
+///
+/// A Result of a boolean value. whether the config file is free of errors, which are already
+/// reported
+pub fn config(action: &ConfigAction) -> Result<bool, GitError> {
+    let path = get_config_path();
+    let file = get_config_file();
+    let content = fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("Failed to read {:?}. Try initializing first.", &path));
+    match action {
+        ConfigAction::Check(output) => {
+            let lints = check_config(&content, is_toml_config(&path));
+            let errors = lints.iter().filter(|l| l.error).count();
+            if *output == OutputFormat::Json {
+                let report = CheckReport {
+                    config_file: file,
+                    errors,
+                    warnings: lints.len() - errors,
+                    lints,
+                };
+                print_json("config check", &report);
+            } else {
+                for lint in &lints {
+                    println!("{lint}");
+                }
+                match lints.len() {
+                    0 => println!("{file} looks good"),
+                    n => println!("{} error(s), {} warning(s)", errors, n - errors),
+                }
+            }
+            return Ok(errors == 0);
+        }
+        ConfigAction::Fmt if is_toml_config(&path) => {
+            warn!("Only {CONFIG_FILE} can be formatted. Use a TOML formatter for {file}.");
+        }
+        ConfigAction::Fmt => {
+            let errors = check_syntax(&content)
+                .0
+                .into_iter()
+                .filter(|l| l.error)
+                .collect::<Vec<Lint>>();
+            if !errors.is_empty() {
+                for lint in &errors {
+                    println!("{lint}");
+                }
+                error!("Fix the errors above before formatting {file}");
+                return Ok(false);
+            }
+            let formatted = format_config(&content);
+            if formatted == content {
+                info!("{file} is already formatted");
+            } else {
+                fs::write(&path, formatted)
+                    .unwrap_or_else(|_| panic!("Failed to write {:?}", &path));
+                info!("Formatted {file}");
+            }
+        }
+        ConfigAction::Migrate => migrate_config()?,
+    }
+    Ok(true)
+}
+
+/// It checks the syntax of every line, then the mappings against the file system: patterns matching
+/// nothing and mappings matching the same files
+///
+/// Arguments:
+///
+/// * `content`: The content of the config file.
+/// * `toml`: Whether it's a structured config file.
+///
+/// Returns:
+///
+/// A vector of Lint structs, ordered by line
+pub fn check_config(content: &str, toml: bool) -> Vec<Lint> {
+    let (mut lints, mappings) = if toml {
+        check_toml_syntax(content)
+    } else {
+        check_syntax(content)
+    };
+    let mut matched: Vec<(usize, HashSet<String>)> = Vec::new();
+    for (line, mapping) in &mappings {
+        let sources = find_mapping_matches(mapping)
+            .into_iter()
+            .map(|m| m.source)
+            .collect::<HashSet<String>>();
+        if sources.is_empty() {
+            lints.push(warning(
+                *line,
+                format!("{} matches no files", mapping.source),
+            ));
+        }
+        for (other_line, other) in &matched {
+            let shared = sources.intersection(other).collect::<Vec<&String>>();
+            if let Some(example) = shared.first() {
+                lints.push(warning(
+                    *line,
+                    format!(
+                        "overlaps with line {} on {} file(s), e.g. {}",
+                        other_line,
+                        shared.len(),
+                        example
+                    ),
+                ));
+            }
+        }
+        matched.push((*line, sources));
+    }
+    lints.sort_by_key(|l| l.line);
+    lints
+}
+
+/// It checks the syntax of every line: settings, mapping separators, glob patterns, capture group
+/// references and destinations
+///
+/// Arguments:
+///
+/// * `content`: The content of the config file.
+///
+/// Returns:
+///
+/// A tuple of the Lint structs and the valid mappings by line, along with their settings
+fn check_syntax(content: &str) -> (Vec<Lint>, Vec<(usize, Mapping)>) {
+    let mut lints = Vec::new();
+    let mut mappings: Vec<(usize, Mapping)> = Vec::new();
+    let mut seen_mapping = false;
+    let mut current_valid = false;
+    for (index, line) in content.lines().enumerate() {
+        let number = index + 1;
+        if !considered_mapping(line) {
+            continue;
+        }
+        let indented = line.starts_with(char::is_whitespace);
+        if line.trim().starts_with('@') {
+            if !line.contains('=') {
+                lints.push(error(
+                    number,
+                    "expected a setting of the form @<key> = <value>",
+                ));
+                continue;
+            }
+            let setting = convert_line_to_setting(line).expect("Failed to parse setting");
+            let setting_lints = check_setting(number, &setting.key, &setting.value, indented);
+            let valid = !setting_lints.iter().any(|l| l.error);
+            lints.extend(setting_lints);
+            if indented && !seen_mapping {
+                lints.push(warning(
+                    number,
+                    "this indented setting follows no mapping, so it applies globally",
+                ));
+            }
+            if let Some((_, mapping)) = mappings
+                .last_mut()
+                .filter(|_| indented && current_valid && valid)
+            {
+                mapping.settings.push(setting);
+            }
+            continue;
+        }
+        seen_mapping = true;
+        current_valid = false;
+        if indented {
+            lints.push(warning(number, "mappings should not be indented"));
+        }
+        let splits = line.split(':').collect::<Vec<&str>>();
+        if splits.len() != 2 || splits.iter().any(|s| s.trim().is_empty()) {
+            lints.push(error(
+                number,
+                "expected a mapping of the form <source> : <destination>",
+            ));
+            continue;
+        }
+        let mapping = convert_line_to_mapping(line.to_string());
+        let mapping_lints = check_mapping(number, &mapping);
+        let valid = !mapping_lints.iter().any(|l| l.error);
+        lints.extend(mapping_lints);
+        if valid {
+            mappings.push((number, mapping));
+            current_valid = true;
+        }
+    }
+    (lints, mappings)
+}
+
+/// It checks a structured config file: its TOML syntax, the structure of its mappings and then every
+/// setting and mapping like the lines of the line based config file
+///
+/// Arguments:
+///
+/// * `content`: The content of the config file.
+///
+/// Returns:
+///
+/// A tuple of the Lint structs and the valid mappings by line, along with their settings
+fn check_toml_syntax(content: &str) -> (Vec<Lint>, Vec<(usize, Mapping)>) {
+    if let Err(e) = content.parse::<DocumentMut>() {
+        let line = e
+            .span()
+            .map(|span| content[..span.start].lines().count().max(1))
+            .unwrap_or(1);
+        return (vec![error(line, e.message().to_string())], Vec::new());
+    }
+    let config = match parse_toml_config(content) {
+        Ok(config) => config,
+        Err(e) => return (vec![error(1, e.to_string())], Vec::new()),
+    };
+    let mut lints = Vec::new();
+    for setting in &config.settings {
+        let line = find_key_line(content, &setting.key);
+        lints.extend(check_setting(line, &setting.key, &setting.value, false));
+    }
+    let mut mappings = Vec::new();
+    for (mapping, line) in config.mappings.into_iter().zip(find_table_lines(content)) {
+        let mut mapping_lints = check_mapping(line, &mapping);
+        for setting in &mapping.settings {
+            mapping_lints.extend(check_setting(line, &setting.key, &setting.value, true));
+        }
+        let valid = !mapping_lints.iter().any(|l| l.error);
+        lints.extend(mapping_lints);
+        if valid {
+            mappings.push((line, mapping));
+        }
+    }
+    (lints, mappings)
+}
+
+/// It finds the number of the line declaring a global setting in a structured config file
+///
+/// Arguments:
+///
+/// * `content`: The content of the config file.
+/// * `key`: The name of the setting.
+///
+/// Returns:
+///
+/// The line number, counting from 1, or 1 if it wasn't found
+fn find_key_line(content: &str, key: &str) -> usize {
+    content
+        .lines()
+        .take_while(|line| !line.trim().starts_with('['))
+        .position(|line| {
+            let line = line.trim().trim_start_matches(['"', '\'']);
+            line.strip_prefix(key)
+                .is_some_and(|rest| rest.trim_start_matches(['"', '\'']).trim().starts_with('='))
+        })
+        .map(|index| index + 1)
+        .unwrap_or(1)
+}
+
+/// It checks a setting's key, where it's declared and its value
+///
+/// Arguments:
+///
+/// * `line`: The line number.
+/// * `key`: The name of the setting.
+/// * `value`: The value of the setting.
+/// * `indented`: Whether it's declared on a mapping.
+///
+/// Returns:
+///
+/// A vector of Lint structs
+fn check_setting(line: usize, key: &str, value: &str, indented: bool) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    if !GLOBAL_KEYS.contains(&key) && !MAPPING_KEYS.contains(&key) {
+        lints.push(warning(line, format!("unknown setting @{key}")));
+    } else if indented && !MAPPING_KEYS.contains(&key) {
+        lints.push(warning(line, format!("@{key} only applies globally")));
+    }
+    let valid = match key {
+        "symlinks" => ["follow", "track", "skip"].contains(&value),
+        "mode" => value == "directory",
+        "allow-binary" | "preserve-owner" => ["true", "false"].contains(&value),
+        "max-file-size" | "max-total-size" => parse_size(value).is_some(),
+        BACKUPS_KEY => value.parse::<usize>().is_ok(),
+        "exclude" => Pattern::new(value).is_ok(),
+        _ => !value.is_empty(),
+    };
+    if !valid {
+        lints.push(error(
+            line,
+            format!("{value:?} is not a valid value of @{key}"),
+        ));
+    }
+    lints
+}
+
+/// It checks a mapping's source pattern, the capture groups its destination refers to and where the
+/// destination is
+///
+/// Arguments:
+///
+/// * `line`: The line number.
+/// * `mapping`: The mapping.
+///
+/// Returns:
+///
+/// A vector of Lint structs
+fn check_mapping(line: usize, mapping: &Mapping) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    if let Err(e) = Pattern::new(&mapping.source) {
+        lints.push(error(
+            line,
+            format!("{} is not a valid pattern: {}", mapping.source, e),
+        ));
+        return lints;
+    }
+    let Some((_, groups)) = split_pattern_groups(&mapping.source) else {
+        lints.push(error(
+            line,
+            format!("{} has an unclosed capture group", mapping.source),
+        ));
+        return lints;
+    };
+    let references = get_group_references(&mapping.destination);
+    for reference in &references {
+        if *reference == 0 || *reference > groups.len() {
+            lints.push(error(
+                line,
+                format!(
+                    "({reference}) refers to no capture group of {}",
+                    mapping.source
+                ),
+            ));
+        }
+    }
+    for group in 1..=groups.len() {
+        if !references.contains(&group) {
+            lints.push(warning(
+                line,
+                format!(
+                    "capture group ({}) {:?} is never used",
+                    group,
+                    groups[group - 1]
+                ),
+            ));
+        }
+    }
+    let destination = resolve_destination(&mapping.destination);
+    let escapes = Path::new(&mapping.destination)
+        .components()
+        .any(|c| c == Component::ParentDir);
+    if escapes || !destination.starts_with(get_working_dir()) {
+        lints.push(warning(
+            line,
+            format!("{} is outside the working directory", mapping.destination),
+        ));
+    }
+    lints
+}
+
+/// It returns the capture group numbers a destination refers to with `(<number>)`
+///
+/// Arguments:
+///
+/// * `destination`: The destination of a mapping.
+///
+/// Returns:
+///
+/// A vector of usize
+fn get_group_references(destination: &str) -> Vec<usize> {
+    destination
+        .split('(')
+        .skip(1)
+        .filter_map(|rest| rest.split_once(')'))
+        .filter_map(|(number, _)| number.parse::<usize>().ok())
+        .collect()
+}
+
+/// It normalises the config file: mappings are written as `<source> : <destination>` with the
+/// separators of consecutive mappings aligned, global settings as `@<key> = <value>`, settings of
+/// mappings indented by four spaces, trailing whitespace and repeated blank lines removed. Comments are
+/// kept as they are.
+///
+/// Arguments:
+///
+/// * `content`: The content of the config file.
+///
+/// Returns:
+///
+/// A String
+pub fn format_config(content: &str) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut block: Vec<(usize, String, String)> = Vec::new();
+    for line in content.lines() {
+        let trimmed = line.trim();
+        let indented = line.starts_with(char::is_whitespace);
+        if considered_mapping(line) && !trimmed.starts_with('@') {
+            let mapping = convert_line_to_mapping(line.to_string());
+            block.push((lines.len(), mapping.source, mapping.destination));
+            lines.push(String::new());
+            continue;
+        }
+        if !(indented && trimmed.starts_with('@')) {
+            align_block(&mut lines, &mut block);
+        }
+        let formatted = match convert_line_to_setting(line) {
+            Some(s) if indented => format!("    @{} = {}", s.key, s.value),
+            Some(s) => format!("@{} = {}", s.key, s.value),
+            None if trimmed.starts_with('#') => line.trim_end().to_string(),
+            None => String::new(),
+        };
+        if formatted.is_empty() && lines.last().is_some_and(|l| l.is_empty()) {
+            continue;
+        }
+        lines.push(formatted);
+    }
+    align_block(&mut lines, &mut block);
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+    lines.join("\n") + "\n"
+}
+
+/// It writes a block of consecutive mappings with their separators aligned
+///
+/// Arguments:
+///
+/// * `lines`: The formatted lines, with placeholders for the mappings of the block.
+/// * `block`: The index, source and destination of each mapping of the block, emptied afterwards.
+fn align_block(lines: &mut [String], block: &mut Vec<(usize, String, String)>) {
+    let width = block
+        .iter()
+        .map(|(_, source, _)| source.chars().count())
+        .max()
+        .unwrap_or(0);
+    for (index, source, destination) in block.drain(..) {
+        lines[index] = format!("{source:<width$} : {destination}");
+    }
+}
+
+/// It creates an error
+///
+/// Arguments:
+///
+/// * `line`: The line number.
+/// * `message`: The description of the problem.
+///
+/// Returns:
+///
+/// A Lint
+fn error(line: usize, message: impl Into<String>) -> Lint {
+    Lint {
+        line,
+        error: true,
+        message: message.into(),
+    }
+}
+
+/// It creates a warning
+///
+/// Arguments:
+///
+/// * `line`: The line number.
+/// * `message`: The description of the problem.
+///
+/// Returns:
+///
+/// A Lint
+fn warning(line: usize, message: impl Into<String>) -> Lint {
+    Lint {
+        line,
+        error: false,
+        message: message.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn create_sources(name: &str, files: &[&str]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("configman-lint-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("Failed to create the test directory");
+        for file in files {
+            fs::write(dir.join(file), "").expect("Failed to create a test file");
+        }
+        dir
+    }
+
+    fn messages(lints: &[Lint]) -> Vec<(usize, bool, &str)> {
+        lints
+            .iter()
+            .map(|l| (l.line, l.error, l.message.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn check_config_accepts_a_valid_config() {
+        let dir = create_sources("valid", &["bashrc"]);
+        let content = format!(
+            "# dotfiles\n@branch = main\n\n{}/bashrc : bash/bashrc\n    @allow-binary = true\n",
+            dir.display()
+        );
+        assert_eq!(check_config(&content, false), Vec::new());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn check_config_reports_syntax_errors() {
+        let content = "@branch\nno separator\n/a/(*) : b/(2)\n@symlinks = sometimes\n";
+        let lints = check_config(content, false);
+        assert_eq!(
+            messages(&lints),
+            vec![
+                (1, true, "expected a setting of the form @<key> = <value>"),
+                (
+                    2,
+                    true,
+                    "expected a mapping of the form <source> : <destination>"
+                ),
+                (3, true, "(2) refers to no capture group of /a/(*)"),
+                (3, false, "capture group (1) \"*\" is never used"),
+                (4, true, "\"sometimes\" is not a valid value of @symlinks"),
+            ]
+        );
+    }
+
+    #[test]
+    fn check_config_reports_misplaced_and_unknown_settings() {
+        let dir = create_sources("settings", &["vimrc"]);
+        let content = format!(
+            "{}/vimrc : vim/vimrc\n    @remote = origin\n    @colour = blue\n",
+            dir.display()
+        );
+        let lints = check_config(&content, false);
+        assert_eq!(
+            messages(&lints),
+            vec![
+                (2, false, "@remote only applies globally"),
+                (3, false, "unknown setting @colour"),
+            ]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn check_config_reports_unmatched_and_overlapping_mappings() {
+        let dir = create_sources("matches", &["a.conf", "b.conf"]);
+        let content = format!(
+            "{0}/(*.conf) : conf/(1)\n{0}/a.conf : a/a.conf\n{0}/missing : missing\n",
+            dir.display()
+        );
+        let lints = check_config(&content, false);
+        let expected = format!(
+            "overlaps with line 1 on 1 file(s), e.g. {}/a.conf",
+            dir.display()
+        );
+        let missing = format!("{}/missing matches no files", dir.display());
+        assert_eq!(
+            messages(&lints),
+            vec![(2, false, expected.as_str()), (3, false, missing.as_str())]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn format_config_aligns_mappings_and_indents_their_settings() {
+        let content = "# dotfiles\n@branch=main\n\n\n/etc/hosts:hosts\n  /home/u/.bashrc  :  bash/bashrc   \n@allow-binary=true\n\n# end   \n\n";
+        assert_eq!(
+            format_config(content),
+            "# dotfiles\n@branch = main\n\n/etc/hosts      : hosts\n/home/u/.bashrc : bash/bashrc\n@allow-binary = true\n\n# end\n"
+        );
+    }
+
+    #[test]
+    fn format_config_keeps_settings_on_their_mapping() {
+        let content = "/a/long/source : a\n\t@exclude = *.bak\n/b : b\n";
+        assert_eq!(
+            format_config(content),
+            "/a/long/source : a\n    @exclude = *.bak\n/b             : b\n"
+        );
+    }
+
+    #[test]
+    fn format_config_is_idempotent() {
+        let content = "@remote = origin\n\n/etc/hosts      : hosts\n    @mode = directory\n/home/u/.bashrc : bash/bashrc\n";
+        assert_eq!(format_config(content), content);
+    }
+}
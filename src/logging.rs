@@ -0,0 +1,192 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+
+/// The logger of the program, set once by `init_logging`.
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// `LogOptions` is a struct that describes how much is logged and where, set with the global
+/// options `-q`, `-v`, `-vv` and `--log-file <path>` given before the action.
+///
+/// Properties:
+///
+/// * `level`: The most detailed level shown on the console.
+/// * `file`: The file every record down to the debug level is appended to, if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogOptions {
+    pub level: LevelFilter,
+    pub file: Option<PathBuf>,
+}
+
+/// It's implementing the `Default` trait for the `LogOptions` struct.
+impl Default for LogOptions {
+    fn default() -> Self {
+        LogOptions {
+            level: LevelFilter::Info,
+            file: None,
+        }
+    }
+}
+
+/// `Logger` is a struct that writes log records to stderr and optionally to a file. Messages are
+/// written as they are, and warnings and errors prefixed with their level, unless the console is
+/// verbose, in which case every record is timestamped like in the log file.
+///
+/// Properties:
+///
+/// * `level`: The most detailed level shown on the console.
+/// * `file`: The log file, if any.
+struct Logger {
+    level: LevelFilter,
+    file: Option<Mutex<File>>,
+}
+
+/// It's implementing the `Log` trait for the `Logger` struct.
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level || (self.file.is_some() && metadata.level() <= Level::Debug)
+    }
+
+    fn log(&self, record: &Record) {
+        let timestamped = format!(
+            "{} {:<5} {}",
+            humantime::format_rfc3339_millis(SystemTime::now()),
+            record.level(),
+            record.args()
+        );
+        if record.level() <= self.level {
+            match record.level() {
+                _ if self.level > LevelFilter::Info => eprintln!("{timestamped}"),
+                Level::Error => eprintln!("error: {}", record.args()),
+                Level::Warn => eprintln!("warning: {}", record.args()),
+                _ => eprintln!("{}", record.args()),
+            }
+        }
+        if let Some(file) = &self.file {
+            if record.level() <= Level::Debug {
+                let mut file = file.lock().unwrap();
+                writeln!(file, "{timestamped}").ok();
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            file.lock().unwrap().flush().ok();
+        }
+    }
+}
+
+/// It takes the global logging options out of the command line arguments. They are only read before
+/// the action, so that the arguments of the action, e.g. a commit message of `-v`, are left as they
+/// are, and `--` ends them early.
+///
+/// Arguments:
+///
+/// * `args`: The command line arguments following the program name.
+///
+/// Returns:
+///
+/// A tuple of the LogOptions struct and the remaining arguments
+pub fn take_log_options(args: &[String]) -> (LogOptions, Vec<String>) {
+    let mut options = LogOptions::default();
+    let mut args = args.iter().peekable();
+    while let Some(arg) = args.peek() {
+        match arg.as_str() {
+            "-q" | "--quiet" => options.level = LevelFilter::Warn,
+            "-v" | "--verbose" => options.level = LevelFilter::Debug,
+            "-vv" => options.level = LevelFilter::Trace,
+            "--log-file" => {
+                args.next();
+                let path = args
+                    .peek()
+                    .expect("--log-file expects the path of the log file");
+                options.file = Some(PathBuf::from(path));
+            }
+            "--" => {
+                args.next();
+                break;
+            }
+            _ => break,
+        }
+        args.next();
+    }
+    (options, args.cloned().collect())
+}
+
+/// It sets up the logger for the rest of the program
+///
+/// Arguments:
+///
+/// * `options`: The logging options.
+pub fn init_logging(options: &LogOptions) {
+    let file = options.file.as_ref().map(|path| {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap_or_else(|_| panic!("Failed to open log file {:?}", &path));
+        Mutex::new(file)
+    });
+    let max_level = match file {
+        Some(_) => options.level.max(LevelFilter::Debug),
+        None => options.level,
+    };
+    let logger = LOGGER.get_or_init(|| Logger {
+        level: options.level,
+        file,
+    });
+    log::set_logger(logger).expect("Failed to set up logging");
+    log::set_max_level(max_level);
+}
+
+/// It returns the most detailed level shown on the console
+///
+/// Returns:
+///
+/// A LevelFilter enum
+pub fn console_level() -> LevelFilter {
+    LOGGER
+        .get()
+        .map(|logger| logger.level)
+        .unwrap_or(LevelFilter::Info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn take(args: &[&str]) -> (LogOptions, Vec<String>) {
+        take_log_options(&args.iter().map(|a| a.to_string()).collect::<Vec<String>>())
+    }
+
+    #[test]
+    fn take_log_options_reads_the_options_before_the_action() {
+        let (options, remaining) = take(&["-vv", "--log-file", "/tmp/c.log", "status"]);
+        assert_eq!(
+            options,
+            LogOptions {
+                level: LevelFilter::Trace,
+                file: Some(PathBuf::from("/tmp/c.log")),
+            }
+        );
+        assert_eq!(remaining, vec!["status"]);
+    }
+
+    #[test]
+    fn take_log_options_leaves_the_arguments_of_the_action() {
+        let (options, remaining) = take(&["-q", "commit", "-m", "-v"]);
+        assert_eq!(options.level, LevelFilter::Warn);
+        assert_eq!(remaining, vec!["commit", "-m", "-v"]);
+    }
+
+    #[test]
+    fn take_log_options_stops_at_a_double_dash() {
+        let (options, remaining) = take(&["--", "-v"]);
+        assert_eq!(options, LogOptions::default());
+        assert_eq!(remaining, vec!["-v"]);
+    }
+}
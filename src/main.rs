@@ -1,23 +1,46 @@
+mod add;
+mod adopt;
+mod backup;
 mod config;
+mod explain;
+mod forget;
 mod fs;
 mod git;
+mod hooks;
+mod journal;
+mod limits;
+mod lint;
+mod logging;
 mod menu;
+mod metadata;
+mod output;
 mod process;
+mod schedule;
+mod secrets;
+mod toml_config;
+mod watch;
 
+use log::error;
+use logging::{init_logging, take_log_options};
 use menu::State;
 use std::env;
+use std::process::exit;
 
-/// It loads the config file, then if there are no arguments, it runs the menu in a loop, otherwise it
-/// runs the menu once and then exits
+/// It sets up logging from the global options, then if there are no other arguments, it runs the menu
+/// in a loop, otherwise it runs the menu once and then exits, with a non-zero status if a git command
+/// or a hook failed
 fn main() {
-    let args = env::args().collect::<Vec<String>>();
-    if args.len() <= 1 || args[0] == "-i" {
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    let (log_options, args) = take_log_options(&args);
+    init_logging(&log_options);
+    if args.is_empty() || args[0] == "-i" {
         let state = State::ActionSelection;
         menu::run(state);
-    } else if args.len() == 2 {
-        let state = State::from(args[1].as_str());
-        menu::run_once(state);
     } else {
-        println!("Failed to understand passed arguments");
+        let state = State::from(&args);
+        if let Err(e) = menu::run_once(state) {
+            error!("{e}");
+            exit(1);
+        }
     }
 }
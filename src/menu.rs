@@ -1,23 +1,63 @@
 use inquire::formatter::OptionFormatter;
-use inquire::{Confirm, Select, Text};
+use inquire::{Confirm, InquireError, MultiSelect, Select, Text};
+use log::{error, info, warn};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::env;
 use std::fmt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
 
-use crate::config::load_config;
+use crate::add::add;
+use crate::adopt::{adopt, get_directory_destinations, AdoptOptions};
+use crate::backup::{backups, BackupAction};
+use crate::config::Mapping;
+use crate::config::{get_config_file, get_config_path, load_config, Config};
+use crate::explain::{explain, ExplainOptions};
+use crate::forget::forget;
 use crate::fs::clean_working_dir;
-use crate::git;
-use crate::process::{get_found_mappings, init_working_dir, link_mappings, track_links};
+use crate::git::{self, ConflictSide, FetchOptions, FetchStrategy, GitError, GitFileStatus};
+use crate::hooks::{has_hooks, run_hooks, Hook};
+use crate::journal::{journaled, undo};
+use crate::limits::LimitViolation;
+use crate::lint::{config, ConfigAction};
+use crate::metadata::{find_drift, read_metadata};
+use crate::output::{is_interactive, print_json, OutputFormat};
+use crate::process::{
+    deploy_mappings, generate_commit_message, get_changed_files, get_found_mappings,
+    get_mappings_for_paths, get_tracked_mappings, init_working_dir, is_mapping_linked, plan_fetch,
+    plan_refresh, refresh_working_dir, stage_changes, store_remote, withhold_changed_secrets,
+    ChangedFile, InitOptions, PlannedAction, RefreshOptions,
+};
+use crate::schedule::{schedule, ScheduleAction};
+use crate::watch::{watch, WatchOptions};
 
-/// Creating an enum called State with the values ActionSelection, Initialize, Clean, Refresh, Commit,
-/// Fetch, Push, and Exit.
+/// Creating an enum called State with the values ActionSelection, Initialize, Remote, Clean, Refresh,
+/// Add, Forget, Adopt, Config, Explain, Status, Commit, Fetch, Pull, Resolve, Push, Watch, Schedule,
+/// Backups, Undo and Exit.
 #[derive(PartialEq, Clone)]
 pub enum State {
     ActionSelection,
-    Initialize,
+    Initialize(InitOptions),
+    Remote(Option<(String, String)>),
     Clean,
-    Refresh,
-    Commit,
-    Fetch,
+    Refresh(RefreshOptions),
+    Add(Vec<String>),
+    Forget(Option<String>),
+    Adopt(AdoptOptions),
+    Config(ConfigAction),
+    Explain(ExplainOptions),
+    Status(OutputFormat),
+    Commit(CommitOptions),
+    Fetch(FetchOptions),
+    Pull(FetchOptions),
+    Resolve,
     Push,
+    Watch(WatchOptions),
+    Schedule(ScheduleAction),
+    Backups(BackupAction),
+    Undo,
     Exit,
 }
 
@@ -26,31 +66,440 @@ impl fmt::Display for State {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             State::ActionSelection => write!(f, "Choose what to do next"),
-            State::Initialize => write!(f, "Initialize current working directory"),
+            State::Initialize(_) => write!(f, "Initialize current working directory"),
+            State::Remote(_) => write!(f, "Manage remotes"),
             State::Clean => write!(f, "Clean items"),
-            State::Refresh => write!(f, "Refresh items"),
-            State::Commit => write!(f, "Commit changes"),
-            State::Fetch => write!(f, "Fetch from remote"),
+            State::Refresh(_) => write!(f, "Refresh items"),
+            State::Add(_) => write!(f, "Add files"),
+            State::Forget(_) => write!(f, "Forget a mapping or files"),
+            State::Adopt(options) if options.undo => write!(f, "Move adopted directories back"),
+            State::Adopt(_) => write!(f, "Adopt directories"),
+            State::Config(ConfigAction::Check(_)) => write!(f, "Check config file"),
+            State::Config(ConfigAction::Fmt) => write!(f, "Format config file"),
+            State::Config(ConfigAction::Migrate) => write!(f, "Migrate config file to TOML"),
+            State::Explain(_) => write!(f, "Explain where a file comes from"),
+            State::Status(_) => write!(f, "Show status"),
+            State::Commit(_) => write!(f, "Commit changes"),
+            State::Fetch(_) => write!(f, "Fetch from remote"),
+            State::Pull(_) => write!(f, "Pull from remote and deploy"),
+            State::Resolve => write!(f, "Resolve conflicts"),
             State::Push => write!(f, "Update remote"),
+            State::Watch(_) => write!(f, "Watch for changes"),
+            State::Schedule(_) => write!(f, "Schedule sync"),
+            State::Backups(BackupAction::List) => write!(f, "List backups"),
+            State::Backups(BackupAction::Restore(_)) => write!(f, "Restore a backup"),
+            State::Undo => write!(f, "Undo the last refresh, pull or clean"),
             State::Exit => write!(f, "Exit"),
         }
     }
 }
 
+/// `RunError` is an enum of the ways running a state can fail.
+///
+/// * `Git`: A git command failed.
+/// * `PreHook`: The pre hook of the state failed, so the state was aborted.
+/// * `PostHook`: The post hook of the state failed after the state ran.
+/// * `InvalidConfig`: The config file has errors, which are already reported.
+#[derive(Debug)]
+pub enum RunError {
+    Git(GitError),
+    PreHook { hook: Hook, state: String },
+    PostHook { hook: Hook, state: String },
+    InvalidConfig(&'static str),
+}
+
+/// Implementing the `Display` trait for the `RunError` enum.
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::Git(e) => write!(f, "{e}"),
+            RunError::PreHook { hook, state } => {
+                write!(f, "The {hook} hook failed. \"{state}\" has been aborted.")
+            }
+            RunError::PostHook { hook, state } => {
+                write!(f, "The {hook} hook failed after \"{state}\".")
+            }
+            RunError::InvalidConfig(file) => write!(f, "{file} has errors"),
+        }
+    }
+}
+
+impl std::error::Error for RunError {}
+
+impl From<GitError> for RunError {
+    fn from(e: GitError) -> Self {
+        RunError::Git(e)
+    }
+}
+
+/// `CommitOptions` is a struct that describes where the commit message comes from.
+///
+/// Properties:
+///
+/// * `message`: The commit message, skipping the prompt.
+/// * `auto`: Whether to commit with the generated message without prompting.
+/// * `output`: The format the result is reported in.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CommitOptions {
+    pub message: Option<String>,
+    pub auto: bool,
+    pub output: OutputFormat,
+}
+
 impl State {
-    pub fn from(str: &str) -> State {
-        match str {
+    /// It converts command line arguments into a `State`. The first argument names the action and
+    /// the remaining ones are options for that action.
+    ///
+    /// Arguments:
+    ///
+    /// * `args`: The command line arguments following the program name.
+    ///
+    /// Returns:
+    ///
+    /// A State enum
+    pub fn from(args: &[String]) -> State {
+        let (name, options) = args
+            .split_first()
+            .map(|(n, o)| (n.as_str(), o))
+            .unwrap_or(("options", &[]));
+        let state = match name {
             "options" => Self::ActionSelection,
-            "init" => State::Initialize,
+            "init" => return State::Initialize(parse_init_options(options)),
+            "remote" => return State::Remote(parse_remote_options(options)),
             "clean" => State::Clean,
-            "refresh" => State::Refresh,
-            "commit" => State::Commit,
-            "fetch" => State::Fetch,
+            "refresh" => return State::Refresh(parse_refresh_options(options)),
+            "add" => return State::Add(options.to_vec()),
+            "forget" if options.len() <= 1 => return State::Forget(options.first().cloned()),
+            "config" => return State::Config(parse_config_action(options)),
+            "adopt" => return State::Adopt(parse_adopt_options(options)),
+            "explain" if !options.is_empty() => {
+                return State::Explain(parse_explain_options(options))
+            }
+            "status" => return State::Status(parse_output_options("status", options)),
+            "commit" => return State::Commit(parse_commit_options(options)),
+            "fetch" => return State::Fetch(parse_fetch_options(options, FetchStrategy::Reset)),
+            "pull" => return State::Pull(parse_fetch_options(options, FetchStrategy::Merge)),
+            "resolve" => State::Resolve,
             "push" => State::Push,
+            "watch" => return State::Watch(parse_watch_options(options)),
+            "schedule" => return State::Schedule(parse_schedule_options(options)),
+            "backups" => return State::Backups(parse_backup_action(options)),
+            "undo" => State::Undo,
             "exit" => State::Exit,
-            _ => panic!("{:?} is not a valid option", &str),
+            _ => panic!("{:?} is not a valid option", &name),
+        };
+        if !options.is_empty() {
+            panic!("{:?} does not accept options {:?}", &name, &options);
+        }
+        state
+    }
+}
+
+/// It parses the options accepted by the `init` action, i.e. `--clone <url>`, `--remote <url>` and
+/// `--name <remote name>`
+///
+/// Arguments:
+///
+/// * `args`: The options following the action name.
+///
+/// Returns:
+///
+/// An InitOptions struct
+fn parse_init_options(args: &[String]) -> InitOptions {
+    let mut options = InitOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--clone" => options.clone_url = args.next().cloned(),
+            "--remote" => options.remote_url = args.next().cloned(),
+            "--name" => options.remote_name = args.next().cloned(),
+            _ => panic!("{:?} is not a valid init option", &arg),
+        }
+    }
+    options
+}
+
+/// It parses the options accepted by the `refresh` action, i.e. `--allow-secrets`, `--dry-run` and
+/// `--output <format>`
+///
+/// Arguments:
+///
+/// * `args`: The options following the action name.
+///
+/// Returns:
+///
+/// A RefreshOptions struct
+fn parse_refresh_options(args: &[String]) -> RefreshOptions {
+    let mut options = RefreshOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--allow-secrets" => options.allow_secrets = true,
+            "--dry-run" => options.dry_run = true,
+            "--output" => options.output = OutputFormat::parse(args.next()),
+            _ => panic!("{:?} is not a valid refresh option", &arg),
+        }
+    }
+    options
+}
+
+/// It parses the options accepted by the `adopt` action, i.e. `--undo` followed by the source
+/// directories to act on
+///
+/// Arguments:
+///
+/// * `args`: The options following the action name.
+///
+/// Returns:
+///
+/// An AdoptOptions struct
+fn parse_adopt_options(args: &[String]) -> AdoptOptions {
+    let mut options = AdoptOptions::default();
+    for arg in args {
+        match arg.as_str() {
+            "--undo" => options.undo = true,
+            _ if arg.starts_with("--") => panic!("{:?} is not a valid adopt option", &arg),
+            _ => options.paths.push(arg.clone()),
+        }
+    }
+    options
+}
+
+/// It parses the options accepted by the `explain` action, i.e. the path to explain and
+/// `--output <format>`
+///
+/// Arguments:
+///
+/// * `args`: The options following the action name.
+///
+/// Returns:
+///
+/// An ExplainOptions struct
+fn parse_explain_options(args: &[String]) -> ExplainOptions {
+    let mut options = ExplainOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => options.output = OutputFormat::parse(args.next()),
+            _ if arg.starts_with("--") => panic!("{:?} is not a valid explain option", &arg),
+            _ if options.path.is_none() => options.path = Some(arg.clone()),
+            _ => panic!("explain accepts a single path, got {:?} too", &arg),
+        }
+    }
+    options
+}
+
+/// It parses the options of actions that only accept `--output <format>`
+///
+/// Arguments:
+///
+/// * `action`: The name of the action.
+/// * `args`: The options following the action name.
+///
+/// Returns:
+///
+/// An OutputFormat enum
+fn parse_output_options(action: &str, args: &[String]) -> OutputFormat {
+    let mut output = OutputFormat::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => output = OutputFormat::parse(args.next()),
+            _ => panic!("{:?} is not a valid {} option", &arg, action),
+        }
+    }
+    output
+}
+
+/// It parses the action following `config`, i.e. `check` with its `--output <format>`, `fmt` or
+/// `migrate`
+///
+/// Arguments:
+///
+/// * `args`: The options following the action name.
+///
+/// Returns:
+///
+/// A ConfigAction enum
+fn parse_config_action(args: &[String]) -> ConfigAction {
+    let (action, options) = args
+        .split_first()
+        .map(|(a, o)| (a.as_str(), o))
+        .unwrap_or(("", &[]));
+    match (action, options) {
+        ("check", _) => ConfigAction::Check(parse_output_options("config check", options)),
+        ("fmt", []) => ConfigAction::Fmt,
+        ("migrate", []) => ConfigAction::Migrate,
+        _ => panic!(
+            "{:?} is not a valid config action. Expected check, fmt or migrate",
+            &args
+        ),
+    }
+}
+
+/// It parses the options accepted by the `remote` action, i.e. nothing to list the remotes or
+/// `add <name> <url>` to add one
+///
+/// Arguments:
+///
+/// * `args`: The options following the action name.
+///
+/// Returns:
+///
+/// An Option of a tuple of the remote name and URL
+fn parse_remote_options(args: &[String]) -> Option<(String, String)> {
+    match args {
+        [] => None,
+        [action, name, url] if action == "add" => Some((name.clone(), url.clone())),
+        _ => panic!("{:?} are not valid remote options", &args),
+    }
+}
+
+/// It parses the options accepted by the `commit` action, i.e. `--message <message>`, `--auto` and
+/// `--output <format>`
+///
+/// Arguments:
+///
+/// * `args`: The options following the action name.
+///
+/// Returns:
+///
+/// A CommitOptions struct
+fn parse_commit_options(args: &[String]) -> CommitOptions {
+    let mut options = CommitOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-m" | "--message" => options.message = args.next().cloned(),
+            "--auto" => options.auto = true,
+            "--output" => options.output = OutputFormat::parse(args.next()),
+            _ => panic!("{:?} is not a valid commit option", &arg),
+        }
+    }
+    options
+}
+
+/// It parses the options accepted by the `watch` action, i.e. `--debounce <duration>` and
+/// `--commit-after <duration>`
+///
+/// Arguments:
+///
+/// * `args`: The options following the action name.
+///
+/// Returns:
+///
+/// A WatchOptions struct
+fn parse_watch_options(args: &[String]) -> WatchOptions {
+    let mut options = WatchOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--debounce" => options.debounce = parse_duration(args.next()),
+            "--commit-after" => options.commit_after = Some(parse_duration(args.next())),
+            _ => panic!("{:?} is not a valid watch option", &arg),
+        }
+    }
+    options
+}
+
+/// It parses the options accepted by the `schedule` action, i.e. `install --every <duration>`,
+/// `remove` and `status`
+///
+/// Arguments:
+///
+/// * `args`: The options following the action name.
+///
+/// Returns:
+///
+/// A ScheduleAction enum
+fn parse_schedule_options(args: &[String]) -> ScheduleAction {
+    match args {
+        [action] if action == "install" => ScheduleAction::Install(Duration::from_secs(60 * 60)),
+        [action, flag, every] if action == "install" && flag == "--every" => {
+            ScheduleAction::Install(parse_duration(Some(every)))
+        }
+        [action] if action == "remove" => ScheduleAction::Remove,
+        [] => ScheduleAction::Status,
+        [action] if action == "status" => ScheduleAction::Status,
+        _ => panic!("{:?} are not valid schedule options", &args),
+    }
+}
+
+/// It parses the action of the `backups` command, i.e. `list` or `restore <id>`
+///
+/// Arguments:
+///
+/// * `args`: The options following the action name.
+///
+/// Returns:
+///
+/// A BackupAction enum
+fn parse_backup_action(args: &[String]) -> BackupAction {
+    match args {
+        [] => BackupAction::List,
+        [action] if action == "list" => BackupAction::List,
+        [action, id] if action == "restore" => BackupAction::Restore(id.clone()),
+        _ => panic!("{:?} are not valid backups options", &args),
+    }
+}
+
+/// It parses a duration given as a number followed by an optional unit of `s`, `m`, `h` or `d`,
+/// defaulting to seconds, e.g. `90`, `5m` or `1h`
+///
+/// Arguments:
+///
+/// * `arg`: The duration argument.
+///
+/// Returns:
+///
+/// A Duration
+fn parse_duration(arg: Option<&String>) -> Duration {
+    let arg = arg.map(|a| a.trim()).unwrap_or_default();
+    let (amount, unit) = arg.split_at(arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len()));
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => panic!("{:?} is not a valid duration", &arg),
+    };
+    match amount.parse::<u64>() {
+        Ok(amount) => Duration::from_secs(amount * seconds),
+        Err(_) => panic!("{:?} is not a valid duration", &arg),
+    }
+}
+
+/// It parses the options accepted by the `fetch` action, i.e. `--rebase`, `--merge`, `--force`,
+/// `--dry-run`, `--remote <name>`, `--branch <name>` and `--output <format>`
+///
+/// Arguments:
+///
+/// * `args`: The options following the action name.
+/// * `strategy`: The strategy used when none is given.
+///
+/// Returns:
+///
+/// A FetchOptions struct
+fn parse_fetch_options(args: &[String], strategy: FetchStrategy) -> FetchOptions {
+    let mut options = FetchOptions {
+        strategy,
+        ..FetchOptions::default()
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--rebase" => options.strategy = FetchStrategy::Rebase,
+            "--merge" => options.strategy = FetchStrategy::Merge,
+            "--reset" => options.strategy = FetchStrategy::Reset,
+            "-f" | "--force" => options.force = true,
+            "--dry-run" => options.dry_run = true,
+            "--remote" => options.remote = args.next().cloned(),
+            "--branch" => options.branch = args.next().cloned(),
+            "--output" => options.output = OutputFormat::parse(args.next()),
+            _ => panic!("{:?} is not a valid fetch option", &arg),
         }
     }
+    options
 }
 
 /// `get_next_action` is a function that returns a next `State` enum.
@@ -63,12 +512,31 @@ impl State {
 fn get_next_action() -> State {
     let formatter: OptionFormatter<State> = &|a| format!("Chose to {a}");
     let actions = vec![
-        State::Initialize,
+        State::Initialize(InitOptions::default()),
+        State::Remote(None),
         State::Clean,
-        State::Refresh,
-        State::Commit,
-        State::Fetch,
+        State::Refresh(RefreshOptions::default()),
+        State::Add(Vec::new()),
+        State::Forget(None),
+        State::Adopt(AdoptOptions::default()),
+        State::Adopt(AdoptOptions {
+            undo: true,
+            ..AdoptOptions::default()
+        }),
+        State::Config(ConfigAction::Check(OutputFormat::Text)),
+        State::Config(ConfigAction::Fmt),
+        State::Config(ConfigAction::Migrate),
+        State::Explain(ExplainOptions::default()),
+        State::Status(OutputFormat::Text),
+        State::Commit(CommitOptions::default()),
+        State::Fetch(FetchOptions::default()),
+        State::Pull(FetchOptions {
+            strategy: FetchStrategy::Merge,
+            ..FetchOptions::default()
+        }),
+        State::Resolve,
         State::Push,
+        State::Undo,
         State::Exit,
     ];
     Select::new("What would you like to do?", actions)
@@ -77,71 +545,1073 @@ fn get_next_action() -> State {
         .expect("Failed to capture selection(s)")
 }
 
-/// It loads the config file, finds all the mappings, and links them to working directory
-fn try_refresh() {
-    clean_working_dir();
-    let config = load_config("config.cmf")
+/// It initializes the working directory. When it isn't a git repo yet and no options were given, the
+/// user is asked whether to clone an existing dotfiles repo or create a new one with a remote
+///
+/// Arguments:
+///
+/// * `options`: The init options.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+fn try_initialize(options: &InitOptions) -> Result<(), GitError> {
+    let mut options = options.clone();
+    if options == InitOptions::default() && !git::is_git_repo_root_dir()? {
+        let choices = vec!["Create a new repo", "Clone an existing repo"];
+        let cloning = Select::new("How would you like to set up this directory?", choices)
+            .prompt()
+            .is_ok_and(|c| c == "Clone an existing repo");
+        let url = Text::new("Remote repo URL: ")
+            .with_help_message("Local paths and file:// URLs work too. Leave empty to skip.")
+            .prompt()
+            .ok()
+            .filter(|url| !url.trim().is_empty())
+            .map(|url| url.trim().to_string());
+        if cloning {
+            options.clone_url = url;
+        } else {
+            options.remote_url = url;
+        }
+    }
+    init_working_dir(&options)
+}
+
+/// It lists the remotes, and either adds the given one or asks the user for one to add. The added
+/// remote is stored in the config file so fetching and pushing use it
+///
+/// Arguments:
+///
+/// * `remote`: The name and URL of the remote to add, if known.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+fn try_remote(remote: &Option<(String, String)>) -> Result<(), GitError> {
+    git::list_remotes()?;
+    let remote = remote.clone().or_else(|| {
+        let name = Text::new("Remote name: ")
+            .with_default(&git::resolve_remote(configured_remote().as_deref()))
+            .prompt()
+            .ok()?;
+        let url = Text::new("Remote repo URL: ")
+            .with_help_message("Leave empty to keep the remotes as they are.")
+            .prompt()
+            .ok()
+            .filter(|url| !url.trim().is_empty())?;
+        Some((name.trim().to_string(), url.trim().to_string()))
+    });
+    if let Some((name, url)) = remote {
+        git::set_remote(&name, &url)?;
+        store_remote(&get_config_path(), &name);
+    }
+    Ok(())
+}
+
+/// It loads the config file if there is one, without failing when the directory isn't initialized
+///
+/// Returns:
+///
+/// An Option<Config>
+fn try_load_config() -> Option<Config> {
+    load_config(&get_config_path()).ok()
+}
+
+/// It returns the remote stored in the config file, if any
+///
+/// Returns:
+///
+/// An Option<String>
+fn configured_remote() -> Option<String> {
+    try_load_config().and_then(|c| c.setting("remote").map(String::from))
+}
+
+/// It cleans the working directory, keeping the directories of directory mappings. The changes are
+/// journaled so that they can be undone.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+fn try_clean() -> Result<(), GitError> {
+    let keep = try_load_config()
+        .map(|c| get_directory_destinations(&c))
+        .unwrap_or_default();
+    journaled("clean", || clean_working_dir(&keep))
+}
+
+/// It adds files to the config file and the working directory, asking for the paths if none were
+/// given and letting the user edit each suggested mapping line
+///
+/// Arguments:
+///
+/// * `paths`: The paths of the files or directories to add.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+fn try_add(paths: &[String]) -> Result<(), GitError> {
+    let paths = if paths.is_empty() {
+        Text::new("Paths to add: ")
+            .with_help_message("Separate multiple paths with spaces.")
+            .prompt()
+            .map(|p| p.split_whitespace().map(String::from).collect())
+            .unwrap_or_default()
+    } else {
+        paths.to_vec()
+    };
+    add(
+        &paths,
+        |line| match Text::new("Mapping to add: ")
+            .with_initial_value(line)
+            .with_help_message("Edit the suggested mapping, or press Esc to skip the file.")
+            .prompt()
+        {
+            Ok(line) => Some(line),
+            Err(InquireError::NotTTY) => Some(line.to_string()),
+            Err(_) => None,
+        },
+        choose_excludes,
+    )
+}
+
+/// It stops tracking a mapping or files, asking for which if not given
+///
+/// Arguments:
+///
+/// * `target`: The mapping or path to forget.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+fn try_forget(target: &Option<String>) -> Result<(), GitError> {
+    let target = target.clone().or_else(|| {
+        Text::new("Mapping or path to forget: ")
+            .with_help_message(
+                "A source pattern, a whole mapping line, or a file or directory path.",
+            )
+            .prompt()
+            .ok()
+            .filter(|t| !t.trim().is_empty())
+    });
+    match target {
+        Some(target) => forget(&target),
+        None => Ok(()),
+    }
+}
+
+/// It explains which mappings put a file where it is, asking for the path if not given
+///
+/// Arguments:
+///
+/// * `options`: The explain options.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+fn try_explain(options: &ExplainOptions) -> Result<(), GitError> {
+    let path = options.path.clone().or_else(|| {
+        Text::new("Path to explain: ")
+            .with_help_message("The source of a file, or its copy in the working directory.")
+            .prompt()
+            .ok()
+            .filter(|p| !p.trim().is_empty())
+    });
+    match path {
+        Some(path) => explain(&path, options.output),
+        None => Ok(()),
+    }
+}
+
+/// It loads the config file and adopts the directory mappings, or moves them back
+///
+/// Arguments:
+///
+/// * `options`: The adopt options.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+fn try_adopt(options: &AdoptOptions) -> Result<(), GitError> {
+    let config = load_config(&get_config_path())
+        .expect("Failed to load config file. Try initializing first.");
+    adopt(&config, options)
+}
+
+/// It loads the config file, finds all the mappings, and links them to working directory. The changes
+/// are journaled so that they can be undone.
+///
+/// Arguments:
+///
+/// * `options`: The refresh options.
+///
+/// Returns:
+///
+/// A Result of a vector of the linked Mapping structs
+fn try_refresh(options: &RefreshOptions) -> Result<Vec<Mapping>, GitError> {
+    let config = load_config(&get_config_path())
         .expect("Failed to load config file. Try initializing first.");
-    let links = link_mappings(&get_found_mappings(&config));
-    track_links(&links);
+    if options.dry_run {
+        show_plan("refresh", &plan_refresh(&config, options)?, options.output);
+        return Ok(Vec::new());
+    }
+    let linked = journaled("refresh", || {
+        refresh_working_dir(&config, options, choose_excludes)
+    })?;
+    if options.output == OutputFormat::Json {
+        let report = RefreshReport {
+            linked: linked.iter().map(LinkedFile::from).collect(),
+        };
+        print_json("refresh", &report);
+    }
+    Ok(linked)
 }
 
-/// It prompts the user for a commit message, and if the user enters a message, it commits the staged
-/// files with that message
-fn try_commit() {
-    match Text::new("Write your commit message here: ").prompt() {
-        Ok(message) => git::commit_staged_files(message.as_str()),
-        Err(e) => println!("Failed to get commit message: {:?}", e),
+/// It reports the changes a dry run found would be made
+///
+/// Arguments:
+///
+/// * `command`: The name of the command that was run dry.
+/// * `plan`: The planned changes.
+/// * `output`: The format the plan is reported in.
+fn show_plan(command: &str, plan: &[PlannedAction], output: OutputFormat) {
+    if output == OutputFormat::Json {
+        let report = PlanReport {
+            actions: plan.to_vec(),
+        };
+        print_json(&format!("{command} --dry-run"), &report);
+        return;
+    }
+    for action in plan {
+        println!("   {action}");
+    }
+    match plan.len() {
+        0 => println!("Nothing would change"),
+        n => println!("{n} change(s) planned, nothing has been changed"),
     }
 }
 
-/// Pushes chages to remote repo.
-/// If there are any files that have been updated both locally and remotely, ask the user if they want
-/// to force push the local changes to the remote repo
-fn try_push() {
-    if git::is_any_file_conflicting() {
-        let force = Confirm::new("Some files have been updated both locally and remotely. Replace remote repo with local changes?")
+/// It asks the user which of the files left out for being over the limits should be excluded from now
+/// on, unless there's no terminal to ask on
+///
+/// Arguments:
+///
+/// * `violations`: The files left out.
+///
+/// Returns:
+///
+/// A vector of exclude patterns
+fn choose_excludes(violations: &[LimitViolation]) -> Vec<String> {
+    if !is_interactive() {
+        return Vec::new();
+    }
+    let sources = violations
+        .iter()
+        .map(|v| v.source.clone())
+        .collect::<Vec<String>>();
+    MultiSelect::new(
+        "Add exclude rules to the config file for these files?",
+        sources,
+    )
+    .with_help_message("Files that aren't excluded are checked again on every refresh.")
+    .prompt()
+    .unwrap_or_default()
+}
+
+/// `StatusReport` is a struct that describes the status of the working directory. It's the JSON
+/// output of `status`.
+///
+/// Properties:
+///
+/// * `clean`: Whether there is nothing to commit.
+/// * `files`: The changed, renamed, conflicting and untracked paths, sorted by path.
+/// * `changes`: The changed files attributed to the applications they belong to.
+/// * `summary`: The commit message generated from the changes, if any.
+/// * `drift`: The source files whose permissions drifted from the recorded ones.
+#[derive(Debug, Serialize)]
+struct StatusReport {
+    clean: bool,
+    files: Vec<StatusEntry>,
+    changes: Vec<ChangedFile>,
+    summary: Option<String>,
+    drift: Vec<StatusDrift>,
+}
+
+/// `StatusEntry` is a struct that describes the git status of a path in the working directory.
+///
+/// Properties:
+///
+/// * `path`: The path, relative to the working directory.
+/// * `index`: The status of the path in the index, as in `git status --porcelain`.
+/// * `worktree`: The status of the path in the working tree, as in `git status --porcelain`.
+/// * `state`: One of `conflict`, `untracked`, `staged` or `modified`.
+/// * `original_path`: The path it was renamed or copied from, if any.
+#[derive(Debug, Serialize)]
+struct StatusEntry {
+    path: PathBuf,
+    index: char,
+    worktree: char,
+    state: &'static str,
+    original_path: Option<PathBuf>,
+}
+
+/// `StatusDrift` is a struct that describes a source file whose permissions drifted.
+///
+/// Properties:
+///
+/// * `source`: The path of the source file.
+/// * `recorded`: The recorded octal mode, followed by `uid:gid` if the owner is preserved.
+/// * `actual`: The current octal mode, followed by `uid:gid` if the owner is preserved.
+#[derive(Debug, Serialize)]
+struct StatusDrift {
+    source: String,
+    recorded: String,
+    actual: String,
+}
+
+/// It describes the state of a changed path
+///
+/// Arguments:
+///
+/// * `status`: The git status of the path.
+///
+/// Returns:
+///
+/// A &str
+fn describe_state(status: &GitFileStatus) -> &'static str {
+    if status.is_conflicting() {
+        "conflict"
+    } else if status.is_untracked() {
+        "untracked"
+    } else if status.is_staged() {
+        "staged"
+    } else {
+        "modified"
+    }
+}
+
+/// It prints every changed, renamed, conflicting and untracked path in the working directory, and
+/// every source file whose permissions drifted from the recorded ones. The JSON output also lists the
+/// changes by application along with the commit message generated from them.
+///
+/// Arguments:
+///
+/// * `output`: The output format.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+fn show_status(output: OutputFormat) -> Result<(), GitError> {
+    let statuses = git::get_status()?;
+    let mut entries = statuses
+        .iter()
+        .filter(|(_, status)| !status.is_ignored())
+        .collect::<Vec<_>>();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    let recorded = read_metadata();
+    let config = try_load_config();
+    let drift = config
+        .as_ref()
+        .filter(|_| !recorded.is_empty())
+        .map(|c| find_drift(&get_tracked_mappings(c), &recorded))
+        .unwrap_or_default();
+    if output == OutputFormat::Json {
+        let changes = get_changed_files(&config.unwrap_or_default(), &statuses);
+        let report = StatusReport {
+            clean: entries.is_empty(),
+            files: entries
+                .iter()
+                .map(|(path, status)| StatusEntry {
+                    path: path.to_path_buf(),
+                    index: status.index_status,
+                    worktree: status.working_tree_status,
+                    state: describe_state(status),
+                    original_path: status.original_path.clone(),
+                })
+                .collect(),
+            summary: generate_commit_message(&changes),
+            changes,
+            drift: drift
+                .iter()
+                .map(|d| StatusDrift {
+                    source: d.source.clone(),
+                    recorded: d.recorded.to_string(),
+                    actual: d.actual.to_string(),
+                })
+                .collect(),
+        };
+        print_json("status", &report);
+        return Ok(());
+    }
+    if entries.is_empty() {
+        println!("Nothing to commit, working directory is clean.");
+    }
+    for (path, status) in entries {
+        let origin = status
+            .original_path
+            .as_ref()
+            .map(|o| format!(" <- {}", o.display()))
+            .unwrap_or_default();
+        println!(
+            "{}{} {:<9} {}{}",
+            status.index_status,
+            status.working_tree_status,
+            describe_state(status),
+            path.display(),
+            origin
+        );
+    }
+    for drift in drift {
+        println!("   {:<9} {}", "mode", drift);
+    }
+    Ok(())
+}
+
+/// `CommitReport` is a struct that describes what was committed. It's the JSON output of `commit`.
+///
+/// Properties:
+///
+/// * `committed`: Whether a commit was made.
+/// * `message`: The commit message, if a commit was made.
+/// * `files`: The committed files attributed to the applications they belong to.
+#[derive(Debug, Default, Serialize)]
+struct CommitReport {
+    committed: bool,
+    message: Option<String>,
+    files: Vec<ChangedFile>,
+}
+
+/// `FetchReport` is a struct that describes the result of `fetch`, and is its JSON output.
+///
+/// Properties:
+///
+/// * `applied`: Whether the remote commits were applied to the local branch.
+#[derive(Debug, Serialize)]
+struct FetchReport {
+    applied: bool,
+}
+
+/// `PullReport` is a struct that describes the result of `pull`, and is its JSON output.
+///
+/// Properties:
+///
+/// * `deployed`: The files deployed from the working directory to their source locations.
+/// * `conflicts`: The paths left conflicting, which postpone the deployment until resolved.
+#[derive(Debug, Serialize)]
+struct PullReport {
+    deployed: Vec<LinkedFile>,
+    conflicts: Vec<String>,
+}
+
+/// `RefreshReport` is a struct that describes the result of `refresh`, and is its JSON output.
+///
+/// Properties:
+///
+/// * `linked`: The files linked from their source locations to the working directory.
+#[derive(Debug, Serialize)]
+struct RefreshReport {
+    linked: Vec<LinkedFile>,
+}
+
+/// `PlanReport` is a struct that describes the changes a dry run of `refresh`, `fetch` or `pull`
+/// found would be made, and is its JSON output.
+///
+/// Properties:
+///
+/// * `actions`: The planned changes.
+#[derive(Debug, Serialize)]
+struct PlanReport {
+    actions: Vec<PlannedAction>,
+}
+
+/// `LinkedFile` is a struct that describes a source file linked with its copy in the working
+/// directory, by `refresh` or `pull`.
+///
+/// Properties:
+///
+/// * `source`: The source path of the file.
+/// * `destination`: The path of the copy in the working directory.
+#[derive(Debug, Serialize)]
+struct LinkedFile {
+    source: String,
+    destination: String,
+}
+
+/// It's implementing the `From` trait to describe a found mapping as a `LinkedFile`.
+impl From<&Mapping> for LinkedFile {
+    fn from(mapping: &Mapping) -> Self {
+        LinkedFile {
+            source: mapping.source.clone(),
+            destination: mapping.destination.clone(),
+        }
+    }
+}
+
+/// It commits the changes, see `commit_changes`, and reports what was committed
+///
+/// Arguments:
+///
+/// * `options`: The commit options.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+fn try_commit(options: &CommitOptions) -> Result<(), GitError> {
+    let report = commit_changes(options)?;
+    if options.output == OutputFormat::Json {
+        print_json("commit", &report);
+    }
+    Ok(())
+}
+
+/// It lists the changed files grouped by mapping, leaving out the ones flagged by the secret scanner,
+/// and, unless a message was given or the generated one should be used as is, lets the user pick which
+/// of them to commit and edit the message generated from the picked files before committing only
+/// those
+///
+/// Arguments:
+///
+/// * `options`: The commit options.
+///
+/// Returns:
+///
+/// A Result of a CommitReport struct
+fn commit_changes(options: &CommitOptions) -> Result<CommitReport, GitError> {
+    let config = try_load_config().unwrap_or_default();
+    if options.message.is_some() || options.auto {
+        let changes = stage_changes(&config)?;
+        let message = options
+            .message
+            .clone()
+            .or_else(|| generate_commit_message(&changes))
+            .unwrap_or_default();
+        let committed = git::commit_staged_files(message.as_str())?;
+        return Ok(match committed {
+            true => CommitReport {
+                committed,
+                message: Some(message),
+                files: changes,
+            },
+            false => CommitReport::default(),
+        });
+    }
+    let changes =
+        withhold_changed_secrets(&config, get_changed_files(&config, &git::get_status()?));
+    if changes.is_empty() {
+        warn!("There are no changed files. Commit has been aborted.");
+        return Ok(CommitReport::default());
+    }
+    let all = (0..changes.len()).collect::<Vec<usize>>();
+    let selected = match MultiSelect::new("Which changes would you like to commit?", changes)
+        .with_default(&all)
+        .with_help_message("Type to filter by mapping, e.g. nvim. Right arrow selects all matches.")
+        .prompt()
+    {
+        Ok(selected) => selected,
+        Err(e) => {
+            error!("Failed to capture selection(s): {:?}", e);
+            return Ok(CommitReport::default());
+        }
+    };
+    let generated = generate_commit_message(&selected).unwrap_or_default();
+    match Text::new("Write your commit message here: ")
+        .with_initial_value(&generated)
+        .prompt()
+    {
+        Ok(message) if message.trim().is_empty() => {
+            warn!("The commit message is empty. Commit has been aborted.");
+            Ok(CommitReport::default())
+        }
+        Ok(message) => {
+            let paths = selected
+                .iter()
+                .map(|c| c.path.display().to_string())
+                .collect::<Vec<String>>();
+            git::commit_files(message.as_str(), &paths)?;
+            Ok(CommitReport {
+                committed: true,
+                message: Some(message),
+                files: selected,
+            })
+        }
+        Err(e) => {
+            error!("Failed to get commit message: {:?}", e);
+            Ok(CommitReport::default())
+        }
+    }
+}
+
+/// It fills in the remote and branch to fetch from the config file, unless they were given
+///
+/// Arguments:
+///
+/// * `options`: The fetch options.
+///
+/// Returns:
+///
+/// A FetchOptions struct
+fn with_configured_target(options: &FetchOptions) -> FetchOptions {
+    let mut options = options.clone();
+    if let Some(config) = try_load_config() {
+        options.remote = options
+            .remote
+            .or_else(|| config.setting("remote").map(String::from));
+        options.branch = options
+            .branch
+            .or_else(|| config.setting("branch").map(String::from));
+    }
+    options
+}
+
+/// It fetches from the remote repo without applying the fetched commits, and reports what applying
+/// them would change, see `plan_fetch`
+///
+/// Arguments:
+///
+/// * `options`: The fetch options.
+/// * `deploy`: Whether the fetched files would be deployed, like `pull` does.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+fn try_plan_fetch(options: &FetchOptions, deploy: bool) -> Result<(), GitError> {
+    let config = try_load_config().unwrap_or_default();
+    let plan = plan_fetch(&config, &with_configured_target(options), deploy)?;
+    let command = if deploy { "pull" } else { "fetch" };
+    show_plan(command, &plan, options.output);
+    Ok(())
+}
+
+/// Fetches from the remote repo. If a hard reset would discard uncommitted changes, ask the user
+/// whether to overwrite them before proceeding, unless there's no terminal to ask on. Agreeing only
+/// discards the changes, local commits the remote lacks still refuse the reset unless forced.
+///
+/// Arguments:
+///
+/// * `options`: The fetch options.
+///
+/// Returns:
+///
+/// A Result of a boolean value. whether the remote commits were applied
+fn try_fetch(options: &FetchOptions) -> Result<bool, GitError> {
+    let mut options = with_configured_target(options);
+    if options.strategy == FetchStrategy::Reset
+        && !options.force
+        && is_interactive()
+        && git::has_uncommitted_changes()?
+    {
+        let discard = Confirm::new("There are uncommitted changes. Discard them?")
             .with_default(false)
-            .with_help_message("This will ensure your remote copy matches the local setup.")
+            .with_help_message("Choose no and fetch with --rebase or --merge to keep local work. Local commits are kept either way, unless forced.")
+            .prompt();
+        options.discard_changes = matches!(discard, Ok(true));
+    }
+    git::fetch(&options)
+}
+
+/// Fetches from the remote repo and then re-applies the working directory to the source locations,
+/// since fetching replaces the files that were hard linked to them. Source files that were linked
+/// before fetching are replaced silently, other differing files are only replaced after asking. The
+/// changes are journaled so that they can be undone, and reported as JSON if asked to.
+///
+/// Arguments:
+///
+/// * `options`: The fetch options.
+///
+/// Returns:
+///
+/// A Result of a vector of the deployed Mapping structs
+fn try_pull(options: &FetchOptions) -> Result<Vec<Mapping>, GitError> {
+    let deployed = journaled("pull", || fetch_and_deploy(options))?;
+    if options.output == OutputFormat::Json {
+        let report = PullReport {
+            deployed: deployed.iter().map(LinkedFile::from).collect(),
+            conflicts: git::get_conflicting_files()?,
+        };
+        print_json("pull", &report);
+    }
+    Ok(deployed)
+}
+
+/// It fetches from the remote repo and deploys the fetched files, see `try_pull`
+///
+/// Arguments:
+///
+/// * `options`: The fetch options.
+///
+/// Returns:
+///
+/// A Result of a vector of the deployed Mapping structs
+fn fetch_and_deploy(options: &FetchOptions) -> Result<Vec<Mapping>, GitError> {
+    let config = load_config(&get_config_path())
+        .expect("Failed to load config file. Try initializing first.");
+    let linked = get_tracked_mappings(&config)
+        .into_iter()
+        .filter(is_mapping_linked)
+        .map(|m| m.source)
+        .collect::<HashSet<String>>();
+    if !try_fetch(options)? {
+        return Ok(Vec::new());
+    }
+    if git::is_any_file_conflicting()? && !try_resolve()? {
+        warn!("Conflicts remain unresolved. Deployment has been postponed.");
+        return Ok(Vec::new());
+    }
+    let config = load_config(&get_config_path())
+        .expect("Failed to load config file. Try initializing first.");
+    let deployed = deploy_mappings(&get_tracked_mappings(&config), |mapping| {
+        linked.contains(&mapping.source) || confirm_overwrite(mapping)
+    });
+    info!(
+        "Deployed {} file(s) from the working directory.",
+        deployed.len()
+    );
+    Ok(deployed)
+}
+
+/// It asks the user whether a source file that differs from its copy in the working directory should
+/// be replaced, optionally showing the differences first
+///
+/// Arguments:
+///
+/// * `mapping`: The mapping whose source differs from its copy.
+///
+/// Returns:
+///
+/// A boolean value. whether the source file may be replaced
+fn confirm_overwrite(mapping: &Mapping) -> bool {
+    let options = vec!["Replace with repo version", "Keep local file", "Show diff"];
+    loop {
+        let choice = Select::new(
+            &format!(
+                "{} differs from the repo version. What should be done?",
+                mapping.source
+            ),
+            options.clone(),
+        )
+        .prompt();
+        match choice {
+            Ok("Replace with repo version") => return true,
+            Ok("Show diff") => {
+                if let Err(e) = git::show_diff(&mapping.source, &mapping.destination) {
+                    error!("{e}");
+                }
+            }
+            _ => return false,
+        }
+    }
+}
+
+/// It walks the user through every conflicting file, letting them keep either side, merge the file
+/// by hand or inspect the differences, and concludes the merge once nothing is left conflicting and the
+/// pre-commit hook agrees. A rebase that stops on the conflicts of a later commit is walked through
+/// again.
+///
+/// Returns:
+///
+/// A Result of a boolean value. whether all conflicts have been resolved
+fn try_resolve() -> Result<bool, GitError> {
+    loop {
+        let conflicts = git::get_conflicting_files()?;
+        if conflicts.is_empty() {
+            if !git::is_merge_in_progress() {
+                return Ok(true);
+            }
+            if !run_pre_hook(&State::Commit(CommitOptions::default()))? {
+                error!(
+                    "The {} hook failed. The merge has not been concluded.",
+                    Hook::PreCommit
+                );
+                return Ok(false);
+            }
+            if let Err(e) = git::continue_merge() {
+                if git::get_conflicting_files()?.is_empty() {
+                    return Err(e);
+                }
+                warn!("Continuing stopped on the conflicts of the next commit.");
+            }
+            continue;
+        }
+        match Select::new(
+            "Which conflicting file would you like to resolve?",
+            conflicts,
+        )
+        .with_help_message("Press escape to stop resolving for now.")
+        .prompt()
+        {
+            Ok(path) => resolve_file(&path)?,
+            Err(_) => return Ok(false),
+        }
+    }
+}
+
+/// It offers the resolution actions for a single conflicting file until one of them resolves it
+///
+/// Arguments:
+///
+/// * `path`: The path of the conflicting file.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+fn resolve_file(path: &str) -> Result<(), GitError> {
+    let actions = vec![
+        "Take ours (local version)",
+        "Take theirs (remote version)",
+        "Merge by hand",
+        "Show diff",
+        "Back",
+    ];
+    loop {
+        let action = Select::new(&format!("How should {path} be resolved?"), actions.clone())
+            .with_help_message("During a rebase ours is the remote version and theirs is local.")
             .prompt();
-        if let Ok(true) = force {
-            git::force_push();
+        match action {
+            Ok("Take ours (local version)") => return git::take_side(path, ConflictSide::Ours),
+            Ok("Take theirs (remote version)") => {
+                return git::take_side(path, ConflictSide::Theirs)
+            }
+            Ok("Merge by hand") => {
+                if merge_by_hand(path)? {
+                    return Ok(());
+                }
+            }
+            Ok("Show diff") => git::exec_git_with_logs(vec!["diff", "--", path])?,
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// It opens a three-way merge of a conflicting file, either in the configured merge tool or in
+/// `$VISUAL`/`$EDITOR` with `diff3` conflict markers, and marks the file resolved if the user is done
+///
+/// Arguments:
+///
+/// * `path`: The path of the conflicting file.
+///
+/// Returns:
+///
+/// A Result of a boolean value. whether the file has been marked resolved
+fn merge_by_hand(path: &str) -> Result<bool, GitError> {
+    if git::get_merge_tool().is_some() {
+        git::open_merge_tool(path)?;
+        return Ok(!git::get_conflicting_files()?.iter().any(|p| p == path));
+    }
+    git::restore_conflict_markers(path)?;
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let mut command = editor.split_whitespace();
+    Command::new(command.next().unwrap_or("vi"))
+        .args(command)
+        .arg(path)
+        .status()
+        .unwrap_or_else(|_| panic!("Failed to open {} in {}", &path, &editor));
+    let markers_left = std::fs::read_to_string(path)
+        .map(|content| content.lines().any(|l| l.starts_with("<<<<<<<")))
+        .unwrap_or(false);
+    let resolved = Confirm::new(&format!("Mark {path} as resolved?"))
+        .with_default(!markers_left)
+        .with_help_message(if markers_left {
+            "The file still contains conflict markers."
+        } else {
+            "The file will be staged as it is now."
+        })
+        .prompt();
+    if let Ok(true) = resolved {
+        git::mark_resolved(path)?;
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+/// Pushes chages to remote repo.
+/// If there are any files that have been updated both locally and remotely, ask the user whether to
+/// resolve the conflicts first or force push the local changes to the remote repo. Without a terminal
+/// to ask on, e.g. when scheduled, nothing is pushed then.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+fn try_push() -> Result<(), GitError> {
+    let remote = git::resolve_remote(configured_remote().as_deref());
+    let conflicting = git::is_any_file_conflicting()?;
+    if conflicting && !is_interactive() {
+        warn!("Some files have been updated both locally and remotely. Run `push` in a terminal to resolve them.");
+        Ok(())
+    } else if conflicting {
+        let actions = vec![
+            "Resolve conflicts",
+            "Replace remote repo with local changes",
+            "Cancel",
+        ];
+        let choice = Select::new(
+            "Some files have been updated both locally and remotely. What should be done?",
+            actions,
+        )
+        .with_help_message("Replacing will ensure your remote copy matches the local setup.")
+        .prompt();
+        match choice {
+            Ok("Resolve conflicts") if try_resolve()? => git::push(&remote),
+            Ok("Replace remote repo with local changes") => git::force_push(&remote),
+            _ => Ok(()),
         }
     } else {
-        git::push();
+        git::push(&remote)
     }
 }
 
-/// It takes a `Config` and a current `State` executes it and returns the next `State`
+/// It returns the hooks run before and after a state, if any. Dry runs change nothing, so they run no
+/// hooks.
 ///
 /// Arguments:
 ///
-/// * `state`: &State - This is the current state of the program.
+/// * `state`: &State - The state to be run.
 ///
 /// Returns:
 ///
-/// A State enum
-pub fn run_once(state: State) -> State {
+/// A tuple of the optional pre and post hooks
+fn get_state_hooks(state: &State) -> (Option<Hook>, Option<Hook>) {
     match state {
-        State::Initialize => init_working_dir(),
-        State::Clean => clean_working_dir(),
-        State::Refresh => try_refresh(),
-        State::Commit => try_commit(),
-        State::Fetch => git::fetch(),
-        State::Push => try_push(),
-        State::ActionSelection => return get_next_action(),
-        State::Exit => return State::Exit,
+        State::Refresh(RefreshOptions { dry_run: true, .. }) => (None, None),
+        State::Pull(FetchOptions { dry_run: true, .. }) => (None, None),
+        State::Refresh(_) => (Some(Hook::PreRefresh), Some(Hook::PostRefresh)),
+        State::Pull(_) => (None, Some(Hook::PostDeploy)),
+        State::Commit(_) => (Some(Hook::PreCommit), None),
+        State::Push => (Some(Hook::PrePush), None),
+        _ => (None, None),
+    }
+}
+
+/// It runs the pre hook of a state with the files the state is about to affect, which are only looked
+/// up if the hook is configured. A failure is left to the caller to report.
+///
+/// Arguments:
+///
+/// * `state`: &State - The state to be run.
+///
+/// Returns:
+///
+/// A Result of a boolean value. whether the state may run
+fn run_pre_hook(state: &State) -> Result<bool, GitError> {
+    let (Some(hook), _) = get_state_hooks(state) else {
+        return Ok(true);
+    };
+    match try_load_config().filter(|c| has_hooks(c, hook)) {
+        Some(config) => Ok(run_hooks(&config, hook, &get_files_before(&config, state)?)),
+        None => Ok(true),
+    }
+}
+
+/// It finds the files a state is about to affect, which are passed to its pre hook
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `state`: &State - The state to be run.
+///
+/// Returns:
+///
+/// A Result of a vector of Mapping structs
+fn get_files_before(config: &Config, state: &State) -> Result<Vec<Mapping>, GitError> {
+    Ok(match state {
+        State::Refresh(_) => get_found_mappings(config),
+        State::Commit(_) => {
+            let paths = get_changed_files(config, &git::get_status()?)
+                .into_iter()
+                .map(|c| c.path)
+                .collect::<Vec<PathBuf>>();
+            get_mappings_for_paths(config, &paths)
+        }
+        State::Push => {
+            let remote = git::resolve_remote(config.setting("remote"));
+            get_mappings_for_paths(config, &git::get_outgoing_files(&remote))
+        }
+        _ => Vec::new(),
+    })
+}
+
+/// It takes a current `State`, executes it surrounded by its hooks and returns the next `State`. A
+/// failing pre hook aborts the state, and so does a failing git command, and either error is returned,
+/// as is a failing post hook.
+///
+/// Arguments:
+///
+/// * `state`: &State - This is the current state of the program.
+///
+/// Returns:
+///
+/// A Result of a State enum, or the RunError that failed the state
+pub fn run_once(state: State) -> Result<State, RunError> {
+    let (pre_hook, post_hook) = get_state_hooks(&state);
+    if let Some(hook) = pre_hook {
+        if !run_pre_hook(&state)? {
+            return Err(RunError::PreHook {
+                hook,
+                state: state.to_string(),
+            });
+        }
+    }
+    let mut affected = Vec::new();
+    match &state {
+        State::Initialize(options) => try_initialize(options)?,
+        State::Remote(remote) => try_remote(remote)?,
+        State::Clean => try_clean()?,
+        State::Refresh(options) => affected = try_refresh(options)?,
+        State::Add(paths) => try_add(paths)?,
+        State::Forget(target) => try_forget(target)?,
+        State::Adopt(options) => try_adopt(options)?,
+        State::Config(action) => {
+            if !config(action)? {
+                return Err(RunError::InvalidConfig(get_config_file()));
+            }
+        }
+        State::Explain(options) => try_explain(options)?,
+        State::Status(output) => show_status(*output)?,
+        State::Commit(options) => try_commit(options)?,
+        State::Fetch(options) if options.dry_run => try_plan_fetch(options, false)?,
+        State::Fetch(options) => {
+            let applied = try_fetch(options)?;
+            if options.output == OutputFormat::Json {
+                print_json("fetch", &FetchReport { applied });
+            }
+        }
+        State::Pull(options) if options.dry_run => try_plan_fetch(options, true)?,
+        State::Pull(options) => affected = try_pull(options)?,
+        State::Resolve => {
+            try_resolve()?;
+        }
+        State::Push => try_push()?,
+        State::Watch(options) => watch(options),
+        State::Schedule(action) => schedule(action),
+        State::Backups(action) => backups(action),
+        State::Undo => undo()?,
+        State::ActionSelection => return Ok(get_next_action()),
+        State::Exit => return Ok(State::Exit),
+    }
+    if let Some(hook) = post_hook {
+        if let Some(config) = try_load_config().filter(|c| has_hooks(c, hook)) {
+            if !run_hooks(&config, hook, &affected) {
+                return Err(RunError::PostHook {
+                    hook,
+                    state: state.to_string(),
+                });
+            }
+        }
     }
-    return State::ActionSelection;
+    Ok(State::ActionSelection)
 }
 
-/// Run the application on loop until the user exits.
+/// Run the application on loop until the user exits. A failing git command is reported and the user
+/// is brought back to the action selection.
 ///
 /// Arguments:
 ///
 /// * `state`: The current state of the program.
 pub fn run(mut state: State) {
     while state != State::Exit {
-        state = run_once(state);
+        state = run_once(state).unwrap_or_else(|e| {
+            error!("{e}");
+            State::ActionSelection
+        });
     }
 }
@@ -0,0 +1,230 @@
+use log::warn;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::{chown, MetadataExt, PermissionsExt};
+use std::path::Path;
+
+use crate::config::{Config, Mapping};
+use crate::fs::get_working_dir;
+use crate::git::{add_file, GitError};
+use crate::process::resolve_destination;
+
+/// The file in the working directory that records the permissions of the mapped files.
+pub const METADATA_FILE: &str = "metadata.cmf";
+
+/// The setting that asks for the owners of the mapped files to be recorded too.
+pub const PRESERVE_OWNER_KEY: &str = "preserve-owner";
+
+/// `FileMetadata` is a struct that describes the permissions of a source file.
+///
+/// Properties:
+///
+/// * `mode`: The permission bits.
+/// * `owner`: The user and group IDs, when ownership is preserved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileMetadata {
+    pub mode: u32,
+    pub owner: Option<(u32, u32)>,
+}
+
+/// It's implementing the `Display` trait for the `FileMetadata` struct.
+impl fmt::Display for FileMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.owner {
+            Some((uid, gid)) => write!(f, "{:04o} {}:{}", self.mode, uid, gid),
+            None => write!(f, "{:04o}", self.mode),
+        }
+    }
+}
+
+/// `MetadataDrift` is a struct that describes a source file whose permissions differ from the
+/// recorded ones.
+///
+/// Properties:
+///
+/// * `source`: The path of the source file.
+/// * `recorded`: The recorded permissions.
+/// * `actual`: The current permissions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataDrift {
+    pub source: String,
+    pub recorded: FileMetadata,
+    pub actual: FileMetadata,
+}
+
+/// It's implementing the `Display` trait for the `MetadataDrift` struct.
+impl fmt::Display for MetadataDrift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is {} but was recorded as {}",
+            self.source, self.actual, self.recorded
+        )
+    }
+}
+
+/// It returns the key a mapping is recorded under, which is its destination relative to the working
+/// directory
+///
+/// Arguments:
+///
+/// * `mapping`: The mapping.
+///
+/// Returns:
+///
+/// A String
+fn metadata_key(mapping: &Mapping) -> String {
+    let destination = resolve_destination(&mapping.destination);
+    destination
+        .strip_prefix(get_working_dir())
+        .unwrap_or(&destination)
+        .display()
+        .to_string()
+}
+
+/// It reads the permissions of a file, along with its owner if asked to. Symbolic links are left to
+/// the files they point to, and directories aren't recorded.
+///
+/// Arguments:
+///
+/// * `path`: The path of the file.
+/// * `with_owner`: Whether to read the owner too.
+///
+/// Returns:
+///
+/// An Option<FileMetadata>
+fn read_file_metadata(path: &str, with_owner: bool) -> Option<FileMetadata> {
+    if Path::new(path).is_symlink() || !Path::new(path).is_file() {
+        return None;
+    }
+    fs::metadata(path).ok().map(|m| FileMetadata {
+        mode: m.mode() & 0o7777,
+        owner: Some((m.uid(), m.gid())).filter(|_| with_owner),
+    })
+}
+
+/// It reads the metadata file of the working directory. Lines are of the form
+/// `<mode> <uid>:<gid> <destination>`, with a `-` in place of an owner that isn't preserved.
+///
+/// Returns:
+///
+/// A BTreeMap of FileMetadata structs by destination
+pub fn read_metadata() -> BTreeMap<String, FileMetadata> {
+    let content = fs::read_to_string(get_working_dir().join(METADATA_FILE)).unwrap_or_default();
+    let mut recorded = BTreeMap::new();
+    for line in content.lines().filter(|l| !l.trim().is_empty()) {
+        let fields = line.splitn(3, ' ').collect::<Vec<&str>>();
+        let parsed = match fields[..] {
+            [mode, owner, destination] => u32::from_str_radix(mode, 8).ok().map(|mode| {
+                let owner = owner.split_once(':').and_then(|(uid, gid)| {
+                    Some((uid.parse::<u32>().ok()?, gid.parse::<u32>().ok()?))
+                });
+                (destination, FileMetadata { mode, owner })
+            }),
+            _ => None,
+        };
+        match parsed {
+            Some((destination, metadata)) => {
+                recorded.insert(destination.to_string(), metadata);
+            }
+            None => warn!("Skipping malformed line in {METADATA_FILE}: {line:?}"),
+        }
+    }
+    recorded
+}
+
+/// It records the permissions of the source files of the mappings in the metadata file, and indexes
+/// the metadata file. Owners are recorded too where `@preserve-owner` is set on the mapping, or
+/// globally.
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `mappings`: The found mappings.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn record_metadata(config: &Config, mappings: &[Mapping]) -> Result<(), GitError> {
+    let recorded = mappings
+        .iter()
+        .filter_map(|m| {
+            let with_owner = m
+                .settings_named(PRESERVE_OWNER_KEY)
+                .last()
+                .copied()
+                .or_else(|| config.setting(PRESERVE_OWNER_KEY))
+                == Some("true");
+            read_file_metadata(&m.source, with_owner).map(|md| (metadata_key(m), md))
+        })
+        .collect::<BTreeMap<String, FileMetadata>>();
+    let content = recorded
+        .iter()
+        .map(|(destination, metadata)| match metadata.owner {
+            Some(_) => format!("{metadata} {destination}\n"),
+            None => format!("{metadata} - {destination}\n"),
+        })
+        .collect::<String>();
+    let path = get_working_dir().join(METADATA_FILE);
+    fs::write(&path, content).unwrap_or_else(|_| panic!("Failed to write {:?}", &path));
+    add_file(&[path.display().to_string()])
+}
+
+/// It restores the recorded permissions of the source files of the mappings. Owners are only restored
+/// when recorded, and failing to change them is reported rather than fatal.
+///
+/// Arguments:
+///
+/// * `mappings`: The deployed mappings.
+pub fn restore_metadata(mappings: &[Mapping]) {
+    let recorded = read_metadata();
+    for drift in find_drift(mappings, &recorded) {
+        let permissions = fs::Permissions::from_mode(drift.recorded.mode);
+        if let Err(e) = fs::set_permissions(&drift.source, permissions) {
+            warn!("Failed to restore the mode of {}: {:?}", &drift.source, e);
+        }
+        if let Some((uid, gid)) = drift
+            .recorded
+            .owner
+            .filter(|_| drift.recorded.owner != drift.actual.owner)
+        {
+            if let Err(e) = chown(&drift.source, Some(uid), Some(gid)) {
+                warn!("Failed to restore the owner of {}: {:?}", &drift.source, e);
+            }
+        }
+    }
+}
+
+/// It compares the permissions of the source files of the mappings with the recorded ones
+///
+/// Arguments:
+///
+/// * `mappings`: The mappings to check.
+/// * `recorded`: The recorded metadata.
+///
+/// Returns:
+///
+/// A vector of MetadataDrift structs
+pub fn find_drift(
+    mappings: &[Mapping],
+    recorded: &BTreeMap<String, FileMetadata>,
+) -> Vec<MetadataDrift> {
+    let mut drifts = Vec::new();
+    for mapping in mappings {
+        let Some(recorded) = recorded.get(&metadata_key(mapping)) else {
+            continue;
+        };
+        let Some(actual) = read_file_metadata(&mapping.source, recorded.owner.is_some()) else {
+            continue;
+        };
+        if &actual != recorded {
+            drifts.push(MetadataDrift {
+                source: mapping.source.clone(),
+                recorded: *recorded,
+                actual,
+            });
+        }
+    }
+    drifts
+}
@@ -0,0 +1,179 @@
+use indicatif::{
+    ProgressBar, ProgressBarIter, ProgressDrawTarget, ProgressIterator, ProgressStyle,
+};
+use log::LevelFilter;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::io::{stdin, stdout, IsTerminal};
+
+use crate::logging::console_level;
+
+/// The version of the JSON output schema. It's increased whenever a field is removed, renamed or
+/// changes its meaning, while fields may be added without increasing it.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The template of the progress bars.
+const PROGRESS_TEMPLATE: &str = "[{percent}%]{prefix} {wide_bar} eta: {eta_precise}";
+
+/// `OutputFormat` is an enum of the formats commands report their results in, chosen with
+/// `--output text|json`.
+///
+/// * `Text`: Human readable text.
+/// * `Json`: A single JSON document on stdout, described at `JsonOutput`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl OutputFormat {
+    /// It parses the value of the `--output` option
+    ///
+    /// Arguments:
+    ///
+    /// * `value`: The value of the option, if given.
+    ///
+    /// Returns:
+    ///
+    /// An OutputFormat enum
+    pub fn parse(value: Option<&String>) -> OutputFormat {
+        match value.map(String::as_str) {
+            Some("text") => OutputFormat::Text,
+            Some("json") => OutputFormat::Json,
+            _ => panic!(
+                "{:?} is not a valid output format. Expected text or json",
+                value
+            ),
+        }
+    }
+}
+
+/// `JsonOutput` is a struct that wraps the JSON output of every command.
+///
+/// Properties:
+///
+/// * `version`: The version of the schema, see `SCHEMA_VERSION`.
+/// * `command`: The name of the command, e.g. `status`.
+/// * `result`: The result of the command.
+///
+/// The result of each command has the following fields, where paths in the working directory are
+/// relative to it unless stated otherwise and absent values are `null`:
+///
+/// * `status`: `clean` (bool), `files` (list of `path`, `index` and `worktree` status letters,
+///   `state` one of `conflict`, `untracked`, `staged` or `modified`, and `original_path`), `changes`
+///   (list of changed files), `summary` (the generated commit message) and `drift` (list of `source`,
+///   `recorded` and `actual` permissions).
+/// * `commit`: `committed` (bool), `message` and `files` (list of changed files).
+/// * `fetch`: `applied` (bool).
+/// * `pull`: `deployed` (list of `source` and `destination`) and `conflicts` (list of paths).
+/// * `refresh`: `linked` (list of `source` and `destination`).
+/// * `refresh --dry-run`, `fetch --dry-run` and `pull --dry-run`: `actions` (list of `action`, `path`
+///   and `source`), where `action` is `remove` or `link` for a refresh, and `remove`, `update` or
+///   `deploy` for a fetch or pull. Only `link` and `deploy` have a `source`. Nothing is changed, but
+///   fetching updates the remote branch.
+/// * `explain`: `path` (absolute) and `matches` (list of `config_file`, `line`, `mapping` with its
+///   `source` and `destination`, `groups`, `source`, the absolute `destination`, `link` and `git`
+///   states in kebab case, e.g. `missing-source` or `not-in-working-dir`).
+/// * `config check`: `config_file`, `errors` and `warnings` (counts) and `lints` (list of `line`,
+///   `error` (bool) and `message`).
+///
+/// A changed file has a `path`, the `group` it's attributed to, the `file` name within the group and
+/// a `verb`, one of `add`, `update`, `remove` or `rename`.
+#[derive(Debug, Serialize)]
+struct JsonOutput<'a, T: Serialize> {
+    version: u32,
+    command: &'a str,
+    result: &'a T,
+}
+
+/// It prints the result of a command as a JSON document
+///
+/// Arguments:
+///
+/// * `command`: The name of the command.
+/// * `result`: The result of the command.
+pub fn print_json<T: Serialize>(command: &str, result: &T) {
+    let output = JsonOutput {
+        version: SCHEMA_VERSION,
+        command,
+        result,
+    };
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&output).expect("Failed to serialize output to JSON")
+    );
+}
+
+/// It checks whether progress should be drawn, i.e. whether stdout is a terminal rather than a pipe
+/// or a file consumed by another program, and the console is neither quiet nor verbose, since log
+/// records would get mixed up with the progress bars
+///
+/// Returns:
+///
+/// A boolean value.
+pub fn shows_progress() -> bool {
+    stdout().is_terminal() && console_level() == LevelFilter::Info
+}
+
+/// It checks whether the user can be asked, i.e. whether stdin is a terminal rather than a timer, a
+/// pipe or a file
+///
+/// Returns:
+///
+/// A boolean value.
+pub fn is_interactive() -> bool {
+    stdin().is_terminal()
+}
+
+/// It returns where progress is drawn, nowhere if it shouldn't be
+///
+/// Returns:
+///
+/// A ProgressDrawTarget
+fn progress_target() -> ProgressDrawTarget {
+    if shows_progress() {
+        ProgressDrawTarget::stderr()
+    } else {
+        ProgressDrawTarget::hidden()
+    }
+}
+
+/// It wraps an iterator in a progress bar
+///
+/// Arguments:
+///
+/// * `iter`: The iterator.
+/// * `prefix`: The description of the work being done.
+///
+/// Returns:
+///
+/// A ProgressBarIter
+pub fn with_progress_bar<I: ExactSizeIterator>(
+    iter: I,
+    prefix: &'static str,
+) -> ProgressBarIter<I> {
+    let bar = ProgressBar::with_draw_target(Some(iter.len() as u64), progress_target())
+        .with_style(ProgressStyle::with_template(PROGRESS_TEMPLATE).unwrap())
+        .with_prefix(prefix);
+    iter.progress_with(bar)
+}
+
+/// It wraps an iterator of unknown length in a spinner
+///
+/// Arguments:
+///
+/// * `iter`: The iterator.
+/// * `message`: The description of the work being done.
+///
+/// Returns:
+///
+/// A ProgressBarIter
+pub fn with_spinner<I: Iterator>(
+    iter: I,
+    message: impl Into<Cow<'static, str>>,
+) -> ProgressBarIter<I> {
+    let spinner = ProgressBar::new_spinner().with_message(message);
+    spinner.set_draw_target(progress_target());
+    iter.progress_with(spinner)
+}
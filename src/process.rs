@@ -1,58 +1,438 @@
-use capturing_glob::Entry;
-use indicatif::{ProgressIterator, ProgressStyle};
+use capturing_glob::{Entry, MatchOptions, Pattern};
+use log::{info, warn};
 use same_file::is_same_file;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
 
+use crate::adopt::{get_directory_destinations, is_adopted, is_directory_mapping, link_directory};
+use crate::backup::back_up;
 use crate::config::Config;
 use crate::config::Mapping;
 use crate::config::SAMPLE_CONFIG_CONTENT;
-use crate::fs::{get_matching_files, get_working_dir, link_path, remove_from_fs};
-use crate::git::{add_file, init_git, is_git_repo_root_dir};
-
-/// It checks if the current working directory has a configuration file, if not, it generates one, then
-/// it checks if the current working directory is a git repository, if not, it initializes one, then it
-/// adds the configuration file to the git repository
-pub fn init_working_dir() {
-    let config_path = get_working_dir().join("config.cmf");
+use crate::config::{append_setting, get_config_path, load_config, write_setting};
+use crate::fs::{
+    clean_working_dir, copy_symlink, escape_pattern, find_cleaned_paths, get_matching_files,
+    get_working_dir, is_same_symlink, link_path, list_files, remove_from_fs, try_hard_link,
+    SymlinkPolicy,
+};
+use crate::git::{
+    add_file, clone_into_working_dir, get_incoming_changes, get_status, init_git,
+    is_git_repo_root_dir, rename_remote, rm_cached, set_remote, stage_files, FetchOptions,
+    GitError, GitFileStatus, GitStatusMap,
+};
+use crate::limits::{enforce_limits, LimitViolation};
+use crate::metadata::{record_metadata, restore_metadata};
+use crate::output::{with_progress_bar, OutputFormat};
+use crate::secrets::{find_secrets, get_scanned_path, ALLOW_SECRET_KEY};
+
+/// `InitOptions` is a struct that describes how the working directory should be initialized.
+///
+/// Properties:
+///
+/// * `clone_url`: The URL of an existing dotfiles repo to clone into the working directory.
+/// * `remote_url`: The URL of the remote to add to a newly created repo.
+/// * `remote_name`: The name of the remote, defaults to `origin`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct InitOptions {
+    pub clone_url: Option<String>,
+    pub remote_url: Option<String>,
+    pub remote_name: Option<String>,
+}
+
+/// `RefreshOptions` is a struct that describes how the working directory should be refreshed.
+///
+/// Properties:
+///
+/// * `allow_secrets`: Whether to index files flagged by the secret scanner anyway.
+/// * `dry_run`: Whether to only report what refreshing would change.
+/// * `output`: The format the result is reported in.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RefreshOptions {
+    pub allow_secrets: bool,
+    pub dry_run: bool,
+    pub output: OutputFormat,
+}
+
+/// It clones an existing repo if asked to, adopting the remote name stored in its configuration file
+/// unless one was given, then it checks if the current working directory has a
+/// configuration file, if not, it generates one, then it checks if the current working directory is a
+/// git repository, if not, it initializes one, then it sets up the remote and stores it in the
+/// configuration file, and finally it adds the configuration file to the git repository
+///
+/// Arguments:
+///
+/// * `options`: The init options.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn init_working_dir(options: &InitOptions) -> Result<(), GitError> {
+    let mut remote = options
+        .remote_name
+        .clone()
+        .unwrap_or_else(|| "origin".to_string());
+    if let Some(url) = &options.clone_url {
+        clone_into_working_dir(url, &remote)?;
+        let configured = load_config(&get_config_path())
+            .ok()
+            .and_then(|c| c.setting("remote").map(String::from));
+        if let Some(configured) = configured.filter(|_| options.remote_name.is_none()) {
+            rename_remote(&remote, &configured)?;
+            remote = configured;
+        }
+    }
+    let config_path = PathBuf::from(get_config_path());
     if !&config_path.exists() {
-        println!("No configuration file found in current working directory.");
+        info!("No configuration file found in current working directory.");
         match fs::write(&config_path, SAMPLE_CONFIG_CONTENT) {
-            Ok(_) => println!("Generated a new config file at: {:?}", &config_path),
+            Ok(_) => info!("Generated a new config file at: {:?}", &config_path),
             Err(e) => panic!("Failed to generated new config file: {:?}", e),
         }
     }
-    if !is_git_repo_root_dir() {
-        init_git();
+    if !is_git_repo_root_dir()? {
+        init_git()?;
+    }
+    if let Some(url) = &options.remote_url {
+        set_remote(&remote, url)?;
+    }
+    if options.clone_url.is_some() || options.remote_url.is_some() {
+        store_remote(&config_path.display().to_string(), &remote);
+    }
+    add_file(&[config_path.display().to_string()])
+}
+
+/// It records the remote in the configuration file unless it is already the configured one
+///
+/// Arguments:
+///
+/// * `config_path`: The path to the configuration file.
+/// * `remote`: The name of the remote.
+pub fn store_remote(config_path: &str, remote: &str) {
+    let configured = load_config(config_path)
+        .ok()
+        .and_then(|c| c.setting("remote").map(String::from));
+    if configured.as_deref() != Some(remote) {
+        write_setting(config_path, "remote", remote)
+            .unwrap_or_else(|e| panic!("Failed to store remote in config file: {:?}", e));
     }
-    add_file(&vec![config_path.display().to_string()]);
 }
 
-/// It takes a vector of paths, converts them to strings, and then sends them to the `add_file` function
-/// in batches of 16
+/// It cleans the working directory, finds all the mappings of the config, links and indexes them with
+/// `index_found_mappings` and records their metadata
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `options`: The refresh options.
+/// * `choose_excludes`: A function that picks the left out files to exclude from now on.
+///
+/// Returns:
+///
+/// A Result of a vector of the linked Mapping structs
+pub fn refresh_working_dir<F>(
+    config: &Config,
+    options: &RefreshOptions,
+    choose_excludes: F,
+) -> Result<Vec<Mapping>, GitError>
+where
+    F: FnMut(&[LimitViolation]) -> Vec<String>,
+{
+    clean_working_dir(&get_directory_destinations(config))?;
+    let found = index_found_mappings(
+        config,
+        get_found_mappings(config),
+        options.allow_secrets,
+        choose_excludes,
+    )?;
+    record_metadata(config, &found)?;
+    Ok(found)
+}
+
+/// It checks the found mappings against the limits, lists the files left out and excludes the ones
+/// chosen from now on, withholds the files that may contain secrets, links the rest to the working
+/// directory and indexes the links
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `found`: The found mappings.
+/// * `allow_secrets`: Whether to index the files that may contain secrets anyway.
+/// * `choose_excludes`: A function that picks the left out files to exclude from now on.
+///
+/// Returns:
+///
+/// A Result of a vector of the linked Mapping structs
+pub fn index_found_mappings<F>(
+    config: &Config,
+    found: Vec<Mapping>,
+    allow_secrets: bool,
+    mut choose_excludes: F,
+) -> Result<Vec<Mapping>, GitError>
+where
+    F: FnMut(&[LimitViolation]) -> Vec<String>,
+{
+    let (found, violations) = enforce_limits(config, found);
+    report_violations(&violations);
+    if !violations.is_empty() {
+        let config_path = get_config_path();
+        for exclude in choose_excludes(&violations) {
+            append_setting(&config_path, "exclude", &escape_pattern(&exclude))
+                .unwrap_or_else(|e| panic!("Failed to add exclude rule to config file: {:?}", e));
+            info!("Excluded {exclude}");
+        }
+    }
+    let found = withhold_secrets(config, found, allow_secrets);
+    link_and_index(config, &found, allow_secrets)?;
+    Ok(found)
+}
+
+/// It lists the files left out for being over the limits
+///
+/// Arguments:
+///
+/// * `violations`: The files left out.
+fn report_violations(violations: &[LimitViolation]) {
+    if violations.is_empty() {
+        return;
+    }
+    warn!("Left out {} file(s) over the limits:", violations.len());
+    for violation in violations {
+        warn!("  {violation}");
+    }
+}
+
+/// It scans the files of the found mappings for secrets, lists the findings and, unless overridden,
+/// leaves out the flagged files
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `found`: The found mappings.
+/// * `allow_secrets`: Whether to keep the flagged files anyway.
+///
+/// Returns:
+///
+/// A vector of the Mapping structs to link or index
+pub fn withhold_secrets(config: &Config, found: Vec<Mapping>, allow_secrets: bool) -> Vec<Mapping> {
+    let findings = find_secrets(config, &found);
+    if findings.is_empty() {
+        return found;
+    }
+    warn!("Found possible secrets:");
+    for finding in &findings {
+        warn!("  {finding}");
+    }
+    if allow_secrets {
+        warn!("Indexing the flagged files anyway.");
+        return found;
+    }
+    warn!(
+        "Refusing to index the flagged files. Allow them with `@{ALLOW_SECRET_KEY} = <path pattern or value>` or run `refresh --allow-secrets`."
+    );
+    found
+        .into_iter()
+        .filter(|m| {
+            let scanned = get_scanned_path(m);
+            !findings.iter().any(|f| f.source == scanned)
+        })
+        .collect()
+}
+
+/// It scans the changed files in the working directory for secrets and leaves out the flagged ones,
+/// so that changes made to linked sources since they were indexed don't get committed unchecked.
+/// Removed files are kept, since removing them can't leak anything.
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `changes`: The changed files.
+///
+/// Returns:
+///
+/// A vector of the ChangedFile structs that may be staged
+pub fn withhold_changed_secrets(config: &Config, changes: Vec<ChangedFile>) -> Vec<ChangedFile> {
+    let wdir = get_working_dir();
+    let paths = changes
+        .iter()
+        .filter(|c| c.verb != "remove")
+        .map(|c| c.path.clone())
+        .collect::<Vec<PathBuf>>();
+    let kept = withhold_secrets(config, get_mappings_for_paths(config, &paths), false)
+        .iter()
+        .map(|m| resolve_destination(&m.destination))
+        .collect::<Vec<PathBuf>>();
+    changes
+        .into_iter()
+        .filter(|c| c.verb == "remove" || kept.contains(&wdir.join(&c.path)))
+        .collect()
+}
+
+/// It stages the changed files of the working directory that aren't flagged by the secret scanner,
+/// deletions included
+///
+/// Arguments:
+///
+/// * `config`: &Config
+///
+/// Returns:
+///
+/// A Result of a vector of the staged ChangedFile structs
+pub fn stage_changes(config: &Config) -> Result<Vec<ChangedFile>, GitError> {
+    let changes = withhold_changed_secrets(config, get_changed_files(config, &get_status()?));
+    let paths = changes
+        .iter()
+        .map(|c| c.path.display().to_string())
+        .collect::<Vec<String>>();
+    stage_files(&paths)?;
+    Ok(changes)
+}
+
+/// It takes a vector of paths, leaves out the ones ignored by git, converts the rest to strings, and
+/// then sends them to the `add_file` function in batches of 16
 ///
 /// Arguments:
 ///
 /// * `paths`: A vector of PathBufs that we want to index.
-pub fn track_links(paths: &Vec<PathBuf>) {
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn track_links(paths: &[PathBuf]) -> Result<(), GitError> {
+    let wdir = get_working_dir();
+    let statuses = get_status()?;
     let path_strs = paths
         .iter()
+        .filter(|p| {
+            let ignored = p
+                .strip_prefix(&wdir)
+                .ok()
+                .and_then(|p| statuses.get(p))
+                .is_some_and(GitFileStatus::is_ignored);
+            if ignored {
+                info!("Skipped indexing {:?}, since git ignores it", p);
+            }
+            !ignored
+        })
         .map(|p| p.display().to_string())
         .collect::<Vec<String>>();
-    for batch in path_strs
-        .chunks(16)
-        .progress_with_style(
-            ProgressStyle::with_template("[{percent}%]{prefix} {wide_bar} eta: {eta_precise}")
-                .unwrap(),
-        )
-        .with_prefix("Indexing files")
-    {
-        add_file(&batch.to_vec());
+    for batch in with_progress_bar(path_strs.chunks(16), "Indexing files") {
+        add_file(batch)?;
     }
+    Ok(())
+}
+
+/// It links the found mappings to the working directory and indexes the links. Adopted directories
+/// are indexed file by file, see `index_directories`.
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `found`: The found mappings, checked against the limits and for secrets already.
+/// * `allow_secrets`: Whether to index the files below adopted directories that may contain secrets.
+///
+/// Returns:
+///
+/// A Result of a vector of the indexed links
+pub fn link_and_index(
+    config: &Config,
+    found: &[Mapping],
+    allow_secrets: bool,
+) -> Result<Vec<PathBuf>, GitError> {
+    let links = link_mappings(found);
+    track_links(&links)?;
+    let adopted = found
+        .iter()
+        .filter(|m| is_directory_mapping(m) && is_adopted(m))
+        .cloned()
+        .collect::<Vec<Mapping>>();
+    index_directories(config, &adopted, allow_secrets)?;
+    Ok(links)
+}
+
+/// It indexes the files below the copies of directory mappings one by one, so that they are checked
+/// against the limits and for secrets like any other file. The files left out are removed from the
+/// index, but stay in the directory.
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `directories`: The adopted directory mappings.
+/// * `allow_secrets`: Whether to index the files that may contain secrets anyway.
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn index_directories(
+    config: &Config,
+    directories: &[Mapping],
+    allow_secrets: bool,
+) -> Result<(), GitError> {
+    let files = directories
+        .iter()
+        .flat_map(get_directory_files)
+        .collect::<Vec<Mapping>>();
+    let (within_limits, violations) = enforce_limits(config, files.clone());
+    for violation in &violations {
+        warn!("Left out {violation}");
+    }
+    let indexed = withhold_secrets(config, within_limits, allow_secrets)
+        .iter()
+        .map(|m| resolve_destination(&m.destination))
+        .collect::<Vec<PathBuf>>();
+    for file in &files {
+        let copy = resolve_destination(&file.destination);
+        if !indexed.contains(&copy) {
+            rm_cached(&copy.display().to_string())?;
+        }
+    }
+    track_links(&indexed)
+}
+
+/// It lists the files below the copy of a directory mapping, symbolic links included but not followed,
+/// as mappings from their paths below the source directory
+///
+/// Arguments:
+///
+/// * `mapping`: The directory mapping.
+///
+/// Returns:
+///
+/// A vector of Mapping structs
+pub fn get_directory_files(mapping: &Mapping) -> Vec<Mapping> {
+    let copy = resolve_destination(&mapping.destination);
+    let mut files = Vec::new();
+    let mut dirs = vec![copy.clone()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = dir.read_dir() else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() && !path.is_symlink() {
+                dirs.push(path);
+                continue;
+            }
+            let relative = path.strip_prefix(&copy).unwrap_or(&path);
+            files.push(Mapping {
+                source: Path::new(&mapping.source)
+                    .join(relative)
+                    .display()
+                    .to_string(),
+                destination: path.display().to_string(),
+                settings: mapping.settings.clone(),
+            });
+        }
+    }
+    files.sort_by(|a, b| a.source.cmp(&b.source));
+    files
 }
 
 /// It takes a vector of mappings, and for each mapping, it ensures that the destination exists as a hardlink to
-/// the source
+/// the source. The copies that are about to be replaced and aren't linked anywhere else are backed up
+/// first. Directory mappings are only checked for being adopted, and their links aren't returned.
 ///
 /// Arguments:
 ///
@@ -60,30 +440,57 @@ pub fn track_links(paths: &Vec<PathBuf>) {
 ///
 /// Returns:
 ///
-/// A vector of PathBufs
-pub fn link_mappings(mappings: &Vec<Mapping>) -> Vec<PathBuf> {
-    let mut linked = Vec::new();
-    for mapping in mappings
+/// A vector of the PathBufs of the updated links
+pub fn link_mappings(mappings: &[Mapping]) -> Vec<PathBuf> {
+    let copies = mappings
         .iter()
-        .progress_with_style(
-            ProgressStyle::with_template("[{percent}%]{prefix} {wide_bar} eta: {eta_precise}")
-                .unwrap(),
-        )
-        .with_prefix("Linking files")
-    {
+        .filter(|m| !is_directory_mapping(m) && !is_mapping_linked(m))
+        .map(|m| resolve_destination(&m.destination))
+        .collect::<Vec<PathBuf>>();
+    back_up("link", &copies);
+    let mut linked = Vec::new();
+    for mapping in with_progress_bar(mappings.iter(), "Linking files") {
         let src = &mapping.source;
         let dest = &mapping.destination;
         let original = PathBuf::from(&src);
-        let link = if Path::new(&dest).is_absolute() {
-            PathBuf::from(&dest)
-        } else {
-            get_working_dir().join(&dest)
+        let link = resolve_destination(dest);
+        let updated = match SymlinkPolicy::of(mapping) {
+            _ if is_directory_mapping(mapping) => {
+                if !is_adopted(mapping) {
+                    warn!("{src} isn't adopted yet. Run `adopt` to move it into the working directory.");
+                }
+                false
+            }
+            Some(SymlinkPolicy::Track) if original.is_symlink() => {
+                ensure_symlink_upto_date(&original, &link)
+            }
+            Some(SymlinkPolicy::Follow) if original.is_symlink() => original
+                .canonicalize()
+                .is_ok_and(|target| ensure_target_linked(&target, &link)),
+            _ => ensure_link_upto_date(&original, &link),
         };
-        if ensure_link_upto_date(&original, &link) {
+        if updated {
             linked.push(link);
         }
     }
-    return linked;
+    linked
+}
+
+/// It resolves a mapping destination, which is either absolute or relative to the working directory
+///
+/// Arguments:
+///
+/// * `dest`: The destination of a mapping.
+///
+/// Returns:
+///
+/// A PathBuf
+pub fn resolve_destination(dest: &str) -> PathBuf {
+    if Path::new(dest).is_absolute() {
+        PathBuf::from(dest)
+    } else {
+        get_working_dir().join(dest)
+    }
 }
 
 /// If the original file exists, then if the link exists, replace it with a new link, else create a new
@@ -102,13 +509,47 @@ pub fn ensure_link_upto_date(original: &PathBuf, link: &PathBuf) -> bool {
     /* Helps in ignoring broken links */
     {
         if link.exists() {
-            replace_existing_with_link(&original, &link)
+            replace_existing_with_link(original, link)
         } else {
-            create_new_link(&original, &link);
+            create_new_link(original, link);
         }
         return true;
     }
-    return false;
+    false
+}
+
+/// It hard-links the target of a followed symbolic link into the working directory. If the target
+/// can't be hard-linked, e.g. since it's on another file system, it warns and leaves the copy as it is.
+///
+/// Arguments:
+///
+/// * `target`: The file the symbolic link points to.
+/// * `link`: The path to the link to be created
+///
+/// Returns:
+///
+/// A boolean value. whether the link is up to date
+fn ensure_target_linked(target: &PathBuf, link: &PathBuf) -> bool {
+    if is_same_file(target, link).unwrap_or(false) {
+        return true;
+    }
+    if link.exists()
+        && !link
+            .canonicalize()
+            .is_ok_and(|l| l.starts_with(get_working_dir()))
+    {
+        return ensure_link_upto_date(target, link);
+    }
+    match try_hard_link(target, link) {
+        Ok(_) => true,
+        Err(e) => {
+            warn!(
+                "Skipped linking {:?} as {:?}, since it can't be hard-linked: {}. Use `@symlinks = track` to track the symbolic link instead.",
+                target, link, e
+            );
+            false
+        }
+    }
 }
 
 /// If the link already exists, and it's not the same file as the original, then remove it and replace
@@ -124,8 +565,8 @@ fn replace_existing_with_link(original: &PathBuf, link: &PathBuf) {
         is_same_file(original.display().to_string(), link.display().to_string()).unwrap_or(false);
     if !same {
         if link.canonicalize().unwrap().starts_with(get_working_dir()) {
-            remove_from_fs(&link);
-            link_path(&original, &link);
+            remove_from_fs(link);
+            link_path(original, link);
         } else {
             panic!(
                 "Failed to link {:?} as {:?}, since a different file already exists there",
@@ -142,12 +583,46 @@ fn replace_existing_with_link(original: &PathBuf, link: &PathBuf) {
 /// * `original`: The path to the original file.
 /// * `link`: The path to the new link
 fn create_new_link(original: &PathBuf, link: &PathBuf) {
-    link.parent().map(|p| fs::create_dir_all(p));
-    link_path(&original, &link);
+    link.parent().map(fs::create_dir_all);
+    link_path(original, link);
+}
+
+/// It makes the link a symbolic link with the same target as the original one, replacing whatever is
+/// there in the working directory
+///
+/// Arguments:
+///
+/// * `original`: The symbolic link to copy.
+/// * `link`: The path to the link to be created
+///
+/// Returns:
+///
+/// A boolean value. whether it created a new link
+fn ensure_symlink_upto_date(original: &PathBuf, link: &PathBuf) -> bool {
+    if is_same_symlink(original, link) {
+        return false;
+    }
+    if link.is_symlink() || link.exists() {
+        remove_from_fs(link);
+    }
+    create_new_symlink(original, link);
+    true
+}
+
+/// It creates a new symbolic link at the given path with the same target as the original one
+///
+/// Arguments:
+///
+/// * `original`: The symbolic link to copy.
+/// * `link`: The path to the new link
+fn create_new_symlink(original: &PathBuf, link: &PathBuf) {
+    link.parent().map(fs::create_dir_all);
+    copy_symlink(original, link);
 }
 
 /// It takes a `Config` and returns a `Vec<Mapping>` where each `Mapping` is a source and destination
-/// file path that have been matched by the config mappings
+/// file path that have been matched by the config mappings, leaving out excluded files and files
+/// inside a directory mapping. The limits are only checked where files are indexed.
 ///
 /// Arguments:
 ///
@@ -158,27 +633,344 @@ fn create_new_link(original: &PathBuf, link: &PathBuf) {
 /// A vector of Mapping structs.
 pub fn get_found_mappings(config: &Config) -> Vec<Mapping> {
     let mut found_mappings = Vec::new();
-    for mapping in config
-        .mappings
+    for mapping in with_progress_bar(config.mappings.iter(), "Finding matching files") {
+        found_mappings.extend(
+            find_mapping_matches(mapping)
+                .into_iter()
+                .filter(|m| !is_excluded(config, m)),
+        );
+    }
+    let directories = found_mappings
         .iter()
-        .progress_with_style(
-            ProgressStyle::with_template("[{percent}%]{prefix} {wide_bar} eta: {eta_precise}")
-                .unwrap(),
+        .filter(|m| is_directory_mapping(m))
+        .map(|m| PathBuf::from(&m.source))
+        .collect::<Vec<PathBuf>>();
+    found_mappings.retain(|m| {
+        is_directory_mapping(m)
+            || !directories
+                .iter()
+                .any(|d| Path::new(&m.source).starts_with(d))
+    });
+    found_mappings
+}
+
+/// It checks whether the source of a found mapping matches an exclude pattern, either global or
+/// declared on its mapping
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `mapping`: The found mapping.
+///
+/// Returns:
+///
+/// A boolean value.
+pub fn is_excluded(config: &Config, mapping: &Mapping) -> bool {
+    config
+        .settings_named("exclude")
+        .into_iter()
+        .chain(mapping.settings_named("exclude"))
+        .any(|e| Pattern::new(e).is_ok_and(|p| p.matches(&mapping.source)))
+}
+
+/// It takes a single config mapping and returns a `Mapping` for every file matched by its source
+/// pattern, with the captured groups substituted in the destination. Symbolic links are kept or left
+/// out according to the symlink policy of the mapping, and directory mappings only match directories.
+///
+/// Arguments:
+///
+/// * `mapping`: The config mapping.
+///
+/// Returns:
+///
+/// A vector of Mapping structs.
+pub fn find_mapping_matches(mapping: &Mapping) -> Vec<Mapping> {
+    let policy = SymlinkPolicy::of(mapping);
+    let mut found_mappings = Vec::new();
+    let matches = get_matching_files(&mapping.source).unwrap_or_else(|e| {
+        panic!(
+            "Failed to match files of {}: {}. Run `config check` for details.",
+            mapping, e
         )
-        .with_prefix("Finding matching files")
-    {
-        for matched in &get_matching_files(&mapping.source).expect("Failed match files") {
-            if !matched.path().is_dir() {
-                let source = matched.path().display().to_string();
-                let destination = substitute_group_values(mapping, matched);
-                found_mappings.push(Mapping {
-                    source,
-                    destination,
-                });
+    });
+    for matched in &matches {
+        let path = matched.path();
+        let considered = match policy {
+            _ if is_directory_mapping(mapping) => path.is_dir(),
+            Some(SymlinkPolicy::Track) if path.is_symlink() => true,
+            Some(SymlinkPolicy::Skip) if path.is_symlink() => false,
+            _ => !path.is_dir(),
+        };
+        if considered {
+            let source = matched.path().display().to_string();
+            let destination = substitute_group_values(mapping, matched);
+            found_mappings.push(Mapping {
+                source,
+                destination,
+                settings: mapping.settings.clone(),
+            });
+        }
+    }
+    found_mappings
+}
+
+/// `ChangedFile` is a struct that describes a changed file in the working directory along with the
+/// application it belongs to.
+///
+/// Properties:
+///
+/// * `path`: The path of the file, relative to the working directory.
+/// * `group`: The application the file belongs to, if it is attributed to a mapping.
+/// * `file`: The name of the file within the application.
+/// * `verb`: What happened to the file, i.e. `add`, `update`, `remove` or `rename`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChangedFile {
+    pub path: PathBuf,
+    pub group: Option<String>,
+    pub file: String,
+    pub verb: &'static str,
+}
+
+/// It's implementing the `Display` trait for the `ChangedFile` struct.
+impl fmt::Display for ChangedFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.group {
+            Some(group) => write!(f, "[{}] {} {}", group, self.verb, self.file),
+            None => write!(f, "{} {}", self.verb, self.file),
+        }
+    }
+}
+
+/// It lists the tracked files with staged or unstaged changes, sorted by path, and attributes each of
+/// them to the application it belongs to. Files are attributed to the config mapping whose found
+/// destinations include them, falling back to the mapping whose destination prefix they fall under for
+/// files that no longer exist.
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `statuses`: The status of the working directory.
+///
+/// Returns:
+///
+/// A vector of ChangedFile structs
+pub fn get_changed_files(config: &Config, statuses: &GitStatusMap) -> Vec<ChangedFile> {
+    let wdir = get_working_dir();
+    let found = config
+        .mappings
+        .iter()
+        .map(|m| {
+            find_mapping_matches(m)
+                .iter()
+                .map(|f| resolve_destination(&f.destination))
+                .collect::<Vec<PathBuf>>()
+        })
+        .collect::<Vec<Vec<PathBuf>>>();
+    let mut changed = statuses
+        .iter()
+        .filter(|(_, status)| {
+            !status.is_ignored() && !status.is_untracked() && !status.is_conflicting()
+        })
+        .collect::<Vec<_>>();
+    changed.sort_by(|a, b| a.0.cmp(b.0));
+    changed
+        .into_iter()
+        .map(|(path, status)| {
+            let full_path = wdir.join(path);
+            let owner = found
+                .iter()
+                .position(|destinations| destinations.contains(&full_path))
+                .map(|i| &config.mappings[i])
+                .or_else(|| find_owner_by_prefix(config, &full_path));
+            let (group, file) = match owner {
+                Some(mapping) => split_group_and_file(mapping, &full_path),
+                None => (None, path.display().to_string()),
+            };
+            let code = match status.index_status {
+                ' ' => status.working_tree_status,
+                code => code,
+            };
+            let verb = match code {
+                'A' | 'C' => "add",
+                'D' => "remove",
+                'R' => "rename",
+                _ => "update",
+            };
+            ChangedFile {
+                path: path.clone(),
+                group,
+                file,
+                verb,
             }
+        })
+        .collect()
+}
+
+/// It builds a commit message from the changed files, grouping them by the application they belong
+/// to, e.g. `nvim: update init.lua; polybar: add launch.sh; remove old mplayer config`
+///
+/// Arguments:
+///
+/// * `changes`: The changed files to describe.
+///
+/// Returns:
+///
+/// An Option<String>, which is None when there are no changes
+pub fn generate_commit_message(changes: &[ChangedFile]) -> Option<String> {
+    let mut groups: Vec<(Option<&str>, Vec<&ChangedFile>)> = Vec::new();
+    for change in changes {
+        let group = change.group.as_deref();
+        match groups.iter_mut().find(|(g, _)| *g == group) {
+            Some((_, grouped)) => grouped.push(change),
+            None => groups.push((group, vec![change])),
         }
     }
-    return found_mappings;
+    let parts = groups
+        .iter()
+        .map(|(group, grouped)| describe_group(*group, grouped))
+        .collect::<Vec<String>>();
+    (!parts.is_empty()).then(|| parts.join("; "))
+}
+
+/// It finds the config mapping with the longest destination prefix a path in the working directory
+/// falls under
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `full_path`: The full path of the file in the working directory.
+///
+/// Returns:
+///
+/// An Option<&Mapping>
+fn find_owner_by_prefix<'a>(config: &'a Config, full_path: &Path) -> Option<&'a Mapping> {
+    config
+        .mappings
+        .iter()
+        .filter(|m| full_path.starts_with(resolve_destination(destination_prefix(m))))
+        .max_by_key(|m| destination_prefix(m).len())
+}
+
+/// It attributes paths in the working directory to the found mappings they were linked from. Paths
+/// no longer found at a source location get a mapping without a source, carrying the settings of the
+/// config mapping whose destination prefix they fall under.
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `paths`: The paths, relative to the working directory.
+///
+/// Returns:
+///
+/// A vector of Mapping structs
+pub fn get_mappings_for_paths(config: &Config, paths: &[PathBuf]) -> Vec<Mapping> {
+    let wdir = get_working_dir();
+    let found = get_found_mappings(config);
+    paths
+        .iter()
+        .map(|path| {
+            let full_path = wdir.join(path);
+            found
+                .iter()
+                .find(|m| resolve_destination(&m.destination) == full_path)
+                .cloned()
+                .unwrap_or_else(|| Mapping {
+                    source: String::new(),
+                    destination: path.display().to_string(),
+                    settings: find_owner_by_prefix(config, &full_path)
+                        .map(|m| m.settings.clone())
+                        .unwrap_or_default(),
+                })
+        })
+        .collect()
+}
+
+/// It returns the literal part of a mapping destination that precedes the first capture group
+///
+/// Arguments:
+///
+/// * `mapping`: The config mapping.
+///
+/// Returns:
+///
+/// A &str
+pub fn destination_prefix(mapping: &Mapping) -> &str {
+    let destination = mapping.destination.as_str();
+    &destination[..destination.find('(').unwrap_or(destination.len())]
+}
+
+/// It splits a destination into the application it belongs to and the file name within it. The
+/// application is the first directory below the literal destination prefix, or the last directory of
+/// the prefix when the file sits directly in it.
+///
+/// Arguments:
+///
+/// * `mapping`: The config mapping the file belongs to.
+/// * `path`: The full destination path of the file.
+///
+/// Returns:
+///
+/// A tuple of the application name, if any, and the file name
+fn split_group_and_file(mapping: &Mapping, path: &Path) -> (Option<String>, String) {
+    let prefix = resolve_destination(destination_prefix(mapping));
+    let rest = path.strip_prefix(&prefix).unwrap_or(path);
+    let mut components = rest.iter().map(|c| c.to_string_lossy().to_string());
+    let first = components.next().unwrap_or_default();
+    let remaining = components.collect::<Vec<String>>();
+    if remaining.is_empty() {
+        let group = prefix
+            .file_name()
+            .filter(|_| prefix != get_working_dir())
+            .map(|n| n.to_string_lossy().to_string());
+        (group, first)
+    } else {
+        (Some(first), remaining.join("/"))
+    }
+}
+
+/// It describes the changes to a group of files, e.g. `nvim: update init.lua, add lua/plugins.lua`,
+/// or `remove old mplayer config` when every file of the group was removed
+///
+/// Arguments:
+///
+/// * `group`: The application the files belong to, if any.
+/// * `changes`: The changed files of the group.
+///
+/// Returns:
+///
+/// A String
+fn describe_group(group: Option<&str>, changes: &[&ChangedFile]) -> String {
+    if let Some(group) = group {
+        if changes.iter().all(|c| c.verb == "remove") {
+            return format!("remove old {group} config");
+        }
+    }
+    let mut verbs: Vec<&str> = Vec::new();
+    for change in changes {
+        if !verbs.contains(&change.verb) {
+            verbs.push(change.verb);
+        }
+    }
+    let description = verbs
+        .iter()
+        .map(|verb| {
+            let mut files = Vec::new();
+            for change in changes.iter().filter(|c| c.verb == *verb) {
+                if !files.contains(&change.file.as_str()) {
+                    files.push(change.file.as_str());
+                }
+            }
+            match files.len() {
+                1..=3 => format!("{verb} {}", files.join(", ")),
+                n => format!("{verb} {n} files"),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+    match group {
+        Some(group) => format!("{group}: {description}"),
+        None => description,
+    }
 }
 
 /// It takes a mapping and a matched entry, and returns a string with all the group values substituted
@@ -192,17 +984,12 @@ pub fn get_found_mappings(config: &Config) -> Vec<Mapping> {
 /// Returns:
 ///
 /// A String
-fn substitute_group_values(mapping: &Mapping, matched: &Entry) -> String {
+pub fn substitute_group_values(mapping: &Mapping, matched: &Entry) -> String {
     let mut destination = mapping.destination.clone();
     let mut group_index: usize = 1;
-    loop {
-        match substitute_group_value(&destination, &matched, group_index) {
-            Some((dest, grp_index)) => {
-                destination = dest;
-                group_index = grp_index;
-            }
-            None => break,
-        }
+    while let Some((dest, grp_index)) = substitute_group_value(&destination, matched, group_index) {
+        destination = dest;
+        group_index = grp_index;
     }
     destination
 }
@@ -220,7 +1007,7 @@ fn substitute_group_values(mapping: &Mapping, matched: &Entry) -> String {
 ///
 /// A tuple of the new destination and the next position to substitute.
 fn substitute_group_value(
-    destination: &String,
+    destination: &str,
     matched: &Entry,
     position: usize,
 ) -> Option<(String, usize)> {
@@ -232,3 +1019,504 @@ fn substitute_group_value(
         )
     })
 }
+
+/// It inverts a config mapping so that it matches files in the working directory instead of the
+/// source locations. Capture groups referenced in the destination become the groups of the new
+/// pattern, and the source is rebuilt from them. Mappings whose source uses wildcards outside of
+/// capture groups, or groups the destination never references, can't be inverted.
+///
+/// Arguments:
+///
+/// * `mapping`: The config mapping to invert.
+///
+/// Returns:
+///
+/// An Option of a Mapping whose source is a pattern over destinations and whose destination is a
+/// template for the source
+pub fn reverse_mapping(mapping: &Mapping) -> Option<Mapping> {
+    let (literals, groups) = split_pattern_groups(&mapping.source)?;
+    if literals.iter().any(|l| l.contains(['*', '?', '['])) {
+        return None;
+    }
+    let mut pattern = String::new();
+    let mut positions = vec![None; groups.len()];
+    let mut rest = mapping.destination.as_str();
+    let mut next_position: usize = 1;
+    while let Some(start) = rest.find('(') {
+        let reference = rest[start + 1..].find(')').and_then(|end| {
+            rest[start + 1..start + 1 + end]
+                .parse::<usize>()
+                .ok()
+                .filter(|n| (1..=groups.len()).contains(n))
+                .map(|n| (n, start + end + 2))
+        });
+        match reference {
+            Some((n, end)) => {
+                pattern.push_str(&rest[..start]);
+                pattern.push_str(&format!("({})", groups[n - 1]));
+                positions[n - 1].get_or_insert(next_position);
+                next_position += 1;
+                rest = &rest[end..];
+            }
+            None => {
+                pattern.push_str(&rest[..=start]);
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    pattern.push_str(rest);
+    let mut source = literals[0].clone();
+    for (position, literal) in positions.iter().zip(&literals[1..]) {
+        source.push_str(&format!("({})", (*position)?));
+        source.push_str(literal);
+    }
+    Some(Mapping {
+        source: pattern,
+        destination: source,
+        settings: mapping.settings.clone(),
+    })
+}
+
+/// It splits a source pattern into the literal text around capture groups and the glob text inside
+/// each group
+///
+/// Arguments:
+///
+/// * `pattern`: The source pattern of a mapping.
+///
+/// Returns:
+///
+/// An Option of a tuple of the literals, which has one more entry than the groups, and the groups
+pub fn split_pattern_groups(pattern: &str) -> Option<(Vec<String>, Vec<String>)> {
+    let mut literals = Vec::new();
+    let mut groups = Vec::new();
+    let mut rest = pattern;
+    while let Some(start) = rest.find('(') {
+        let end = start + rest[start..].find(')')?;
+        literals.push(rest[..start].to_string());
+        groups.push(rest[start + 1..end].to_string());
+        rest = &rest[end + 1..];
+    }
+    literals.push(rest.to_string());
+    Some((literals, groups))
+}
+
+/// It finds every mapped file, both those found at the source locations and those that only exist in
+/// the working directory, e.g. files added by someone else and fetched from the remote repo
+///
+/// Arguments:
+///
+/// * `config`: &Config
+///
+/// Returns:
+///
+/// A vector of Mapping structs, with sources at the original locations and destinations in the
+/// working directory
+pub fn get_tracked_mappings(config: &Config) -> Vec<Mapping> {
+    let reversed = Config {
+        mappings: config.mappings.iter().filter_map(reverse_mapping).collect(),
+        settings: config.settings.clone(),
+    };
+    let mut tracked = get_found_mappings(config);
+    for found in get_found_mappings(&reversed) {
+        let destination = found.source;
+        if !tracked.iter().any(|m| m.destination == destination) {
+            tracked.push(Mapping {
+                source: found.destination,
+                destination,
+                settings: found.settings,
+            });
+        }
+    }
+    tracked
+}
+
+/// It checks whether the source of a mapping is currently the same file as its copy in the working
+/// directory, or a symbolic link with the same target as its copy
+///
+/// Arguments:
+///
+/// * `mapping`: The mapping to check.
+///
+/// Returns:
+///
+/// A boolean value.
+pub fn is_mapping_linked(mapping: &Mapping) -> bool {
+    let copy = resolve_destination(&mapping.destination);
+    is_same_symlink(Path::new(&mapping.source), &copy)
+        || is_same_file(&mapping.source, &copy).unwrap_or(false)
+}
+
+/// It takes a vector of mappings and, for each one whose copy exists in the working directory,
+/// ensures that the source location is a hard link to that copy, or a symbolic link with the same
+/// target if the copy is one. Missing sources of directory mappings are linked to their copies.
+/// Sources that differ from the copy are only replaced if `overwrite` agrees, which is asked for every
+/// one of them before deploying starts. The recorded permissions of the linked sources are restored
+/// afterwards.
+///
+/// Arguments:
+///
+/// * `mappings`: A vector of Mapping structs.
+/// * `overwrite`: Decides whether a differing source file may be replaced.
+///
+/// Returns:
+///
+/// A vector of the deployed Mapping structs
+pub fn deploy_mappings(
+    mappings: &[Mapping],
+    mut overwrite: impl FnMut(&Mapping) -> bool,
+) -> Vec<Mapping> {
+    let kept = mappings
+        .iter()
+        .filter(|m| differs_from_copy(m) && !overwrite(m))
+        .map(|m| m.source.clone())
+        .collect::<HashSet<String>>();
+    let mut deployed = Vec::new();
+    for mapping in with_progress_bar(mappings.iter(), "Deploying files") {
+        let copy = resolve_destination(&mapping.destination);
+        let target = PathBuf::from(&mapping.source);
+        if is_directory_mapping(mapping) {
+            if copy.is_dir() && !(target.exists() || target.is_symlink()) {
+                link_directory(&copy, &target);
+                deployed.push(mapping.clone());
+            }
+            continue;
+        }
+        let tracked_symlink = copy.is_symlink();
+        if !(copy.is_file() || tracked_symlink) || is_mapping_linked(mapping) {
+            continue;
+        }
+        if target.exists() || target.is_symlink() {
+            if kept.contains(&mapping.source) {
+                continue;
+            }
+            remove_from_fs(&target);
+        }
+        if tracked_symlink {
+            create_new_symlink(&copy, &target);
+        } else {
+            create_new_link(&copy, &target);
+        }
+        deployed.push(mapping.clone());
+    }
+    let linked = mappings
+        .iter()
+        .filter(|m| is_mapping_linked(m))
+        .cloned()
+        .collect::<Vec<Mapping>>();
+    restore_metadata(&linked);
+    deployed
+}
+
+/// It checks whether deploying a mapping would replace a source file that differs from its copy in the
+/// working directory
+///
+/// Arguments:
+///
+/// * `mapping`: The mapping to deploy.
+///
+/// Returns:
+///
+/// A boolean value.
+fn differs_from_copy(mapping: &Mapping) -> bool {
+    let copy = resolve_destination(&mapping.destination);
+    let target = Path::new(&mapping.source);
+    let tracked_symlink = copy.is_symlink();
+    !is_directory_mapping(mapping)
+        && (copy.is_file() || tracked_symlink)
+        && (target.exists() || target.is_symlink())
+        && !is_mapping_linked(mapping)
+        && (tracked_symlink || fs::read(target).ok() != fs::read(&copy).ok())
+}
+
+/// `PlannedAction` is a struct that describes a change a dry run found would be made.
+///
+/// Properties:
+///
+/// * `action`: What would be done, i.e. `link`, `remove`, `update` or `deploy`.
+/// * `path`: The path in the working directory, relative to it.
+/// * `source`: The source location the path is linked from or deployed to, if any.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlannedAction {
+    pub action: &'static str,
+    pub path: String,
+    pub source: Option<String>,
+}
+
+/// It's implementing the `Display` trait for the `PlannedAction` struct.
+impl fmt::Display for PlannedAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.source {
+            Some(source) if self.action == "link" => {
+                write!(f, "{:<9} {} <- {}", self.action, self.path, source)
+            }
+            Some(source) => write!(f, "{:<9} {} -> {}", self.action, self.path, source),
+            None => write!(f, "{:<9} {}", self.action, self.path),
+        }
+    }
+}
+
+/// It plans a refresh without changing anything: the files cleaning would remove from the working
+/// directory that aren't linked again, and the found files that would be linked, after the limits and
+/// the secret scanner left some out
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `options`: The refresh options.
+///
+/// Returns:
+///
+/// A Result of a vector of PlannedAction structs
+pub fn plan_refresh(
+    config: &Config,
+    options: &RefreshOptions,
+) -> Result<Vec<PlannedAction>, GitError> {
+    let wdir = get_working_dir();
+    let relative = |path: &Path| {
+        path.strip_prefix(&wdir)
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    };
+    let (found, violations) = enforce_limits(config, get_found_mappings(config));
+    report_violations(&violations);
+    let found = withhold_secrets(config, found, options.allow_secrets)
+        .into_iter()
+        .filter(|m| !is_directory_mapping(m))
+        .collect::<Vec<Mapping>>();
+    let linked = found
+        .iter()
+        .map(|m| resolve_destination(&m.destination))
+        .collect::<HashSet<PathBuf>>();
+    let mut plan = find_cleaned_paths(&get_directory_destinations(config))?
+        .iter()
+        .flat_map(|path| list_files(path))
+        .filter(|path| !linked.contains(path))
+        .map(|path| PlannedAction {
+            action: "remove",
+            path: relative(&path),
+            source: None,
+        })
+        .collect::<Vec<PlannedAction>>();
+    plan.extend(
+        found
+            .iter()
+            .filter(|m| !is_mapping_linked(m))
+            .map(|m| PlannedAction {
+                action: "link",
+                path: relative(&resolve_destination(&m.destination)),
+                source: Some(m.source.clone()),
+            }),
+    );
+    Ok(plan)
+}
+
+/// It plans a fetch without applying it: the files the fetched commits would remove or update in the
+/// working directory, and if they are deployed, the source locations they would be deployed to
+///
+/// Arguments:
+///
+/// * `config`: &Config
+/// * `options`: The fetch options.
+/// * `deploy`: Whether the fetched files are deployed, like `pull` does.
+///
+/// Returns:
+///
+/// A Result of a vector of PlannedAction structs
+pub fn plan_fetch(
+    config: &Config,
+    options: &FetchOptions,
+    deploy: bool,
+) -> Result<Vec<PlannedAction>, GitError> {
+    let reversed = config
+        .mappings
+        .iter()
+        .filter_map(reverse_mapping)
+        .collect::<Vec<Mapping>>();
+    Ok(get_incoming_changes(options)?
+        .into_iter()
+        .map(|(status, path)| {
+            let source = find_deployed_source(&reversed, &path)
+                .filter(|m| deploy && status != 'D' && !is_excluded(config, m))
+                .map(|m| m.source);
+            let action = match (status, &source) {
+                ('D', _) => "remove",
+                (_, Some(_)) => "deploy",
+                (_, None) => "update",
+            };
+            PlannedAction {
+                action,
+                path,
+                source,
+            }
+        })
+        .collect())
+}
+
+/// It finds the source location a file in the working directory would be deployed to with the reversed
+/// mappings, even if the file doesn't exist yet
+///
+/// Arguments:
+///
+/// * `reversed`: The reversed config mappings, see `reverse_mapping`.
+/// * `path`: The path of the file, relative to the working directory.
+///
+/// Returns:
+///
+/// An Option of the Mapping from the source location to the file
+fn find_deployed_source(reversed: &[Mapping], path: &str) -> Option<Mapping> {
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    let full_path = get_working_dir().join(path).display().to_string();
+    reversed.iter().find_map(|mapping| {
+        let pattern = Pattern::new(&mapping.source).ok()?;
+        let matched = pattern
+            .captures_with(path, &options)
+            .or_else(|| pattern.captures_with(&full_path, &options))?;
+        Some(Mapping {
+            source: substitute_group_values(mapping, &matched),
+            destination: path.to_string(),
+            settings: mapping.settings.clone(),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(source: &str, destination: &str) -> Mapping {
+        Mapping {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            settings: Vec::new(),
+        }
+    }
+
+    fn change(group: Option<&str>, file: &str, verb: &'static str) -> ChangedFile {
+        ChangedFile {
+            path: PathBuf::from(file),
+            group: group.map(String::from),
+            file: file.to_string(),
+            verb,
+        }
+    }
+
+    #[test]
+    fn reverse_mapping_swaps_the_groups_into_the_destination() {
+        let reversed = reverse_mapping(&mapping("/home/u/.config/(*)/(*.sh)", "sh/(1)/(2)"));
+        assert_eq!(
+            reversed,
+            Some(mapping("sh/(*)/(*.sh)", "/home/u/.config/(1)/(2)"))
+        );
+    }
+
+    #[test]
+    fn reverse_mapping_follows_the_order_of_the_references() {
+        let reversed = reverse_mapping(&mapping("/srv/(*)/(*)", "apps/(2)/(1)"));
+        assert_eq!(reversed, Some(mapping("apps/(*)/(*)", "/srv/(2)/(1)")));
+    }
+
+    #[test]
+    fn reverse_mapping_keeps_literal_mappings() {
+        let reversed = reverse_mapping(&mapping("/home/u/.bashrc", "bash/bashrc"));
+        assert_eq!(reversed, Some(mapping("bash/bashrc", "/home/u/.bashrc")));
+    }
+
+    #[test]
+    fn reverse_mapping_refuses_wildcards_outside_of_groups() {
+        let reversed = reverse_mapping(&mapping("/home/u/.config/**/(*.png)", "images/(1)"));
+        assert_eq!(reversed, None);
+    }
+
+    #[test]
+    fn reverse_mapping_refuses_unreferenced_groups() {
+        let reversed = reverse_mapping(&mapping("/srv/(*)/(*)", "apps/(1)"));
+        assert_eq!(reversed, None);
+    }
+
+    #[test]
+    fn generate_commit_message_describes_each_group() {
+        let changes = vec![
+            change(Some("nvim"), "init.lua", "update"),
+            change(Some("zsh"), "zshrc", "add"),
+            change(Some("nvim"), "plugins.lua", "update"),
+            change(Some("nvim"), "old.vim", "remove"),
+        ];
+        assert_eq!(
+            generate_commit_message(&changes).as_deref(),
+            Some("nvim: update init.lua, plugins.lua, remove old.vim; zsh: add zshrc")
+        );
+    }
+
+    #[test]
+    fn generate_commit_message_counts_more_than_three_files() {
+        let changes = ["a", "b", "c", "d"]
+            .iter()
+            .map(|f| change(Some("fonts"), f, "add"))
+            .collect::<Vec<ChangedFile>>();
+        assert_eq!(
+            generate_commit_message(&changes).as_deref(),
+            Some("fonts: add 4 files")
+        );
+    }
+
+    #[test]
+    fn generate_commit_message_phrases_removed_groups() {
+        let changes = vec![
+            change(Some("i3"), "config", "remove"),
+            change(Some("i3"), "status", "remove"),
+        ];
+        assert_eq!(
+            generate_commit_message(&changes).as_deref(),
+            Some("remove old i3 config")
+        );
+    }
+
+    #[test]
+    fn generate_commit_message_leaves_out_missing_groups() {
+        let changes = vec![change(None, "notes.txt", "update")];
+        assert_eq!(
+            generate_commit_message(&changes).as_deref(),
+            Some("update notes.txt")
+        );
+        assert_eq!(generate_commit_message(&[]), None);
+    }
+
+    #[test]
+    fn find_deployed_source_reverses_the_path_of_a_copy() {
+        let reversed = [
+            reverse_mapping(&mapping("/home/u/.config/(*)/(*.sh)", "sh/(1)/(2)")).unwrap(),
+            reverse_mapping(&mapping("/home/u/.bashrc", "bash/bashrc")).unwrap(),
+        ];
+        let source = |path: &str| find_deployed_source(&reversed, path).map(|m| m.source);
+        assert_eq!(
+            source("sh/zsh/env.sh").as_deref(),
+            Some("/home/u/.config/zsh/env.sh")
+        );
+        assert_eq!(source("bash/bashrc").as_deref(), Some("/home/u/.bashrc"));
+        assert_eq!(source("sh/zsh/nested/env.sh"), None);
+        assert_eq!(source("config.cmf"), None);
+    }
+
+    #[test]
+    fn planned_actions_show_where_files_come_from_or_go() {
+        let action = |action, source: Option<&str>| PlannedAction {
+            action,
+            path: "bash/bashrc".to_string(),
+            source: source.map(String::from),
+        };
+        assert_eq!(
+            action("link", Some("/home/u/.bashrc")).to_string(),
+            "link      bash/bashrc <- /home/u/.bashrc"
+        );
+        assert_eq!(
+            action("deploy", Some("/home/u/.bashrc")).to_string(),
+            "deploy    bash/bashrc -> /home/u/.bashrc"
+        );
+        assert_eq!(action("remove", None).to_string(), "remove    bash/bashrc");
+    }
+}
@@ -0,0 +1,276 @@
+use log::{error, info};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use crate::fs::{get_working_dir, remove_from_fs};
+
+/// The name shared by the service and timer units.
+const UNIT_NAME: &str = "configman-sync";
+
+/// The environment variable that overrides the directory the units are written to. When it is set,
+/// the units are only written and `systemctl` is left alone.
+const UNIT_DIR_VAR: &str = "CONFIGMAN_SYSTEMD_DIR";
+
+/// The actions run by the service, in order. Each one is a separate invocation, which runs its hooks,
+/// without a terminal, so they don't prompt.
+const SYNC_ACTIONS: [&str; 3] = ["refresh", "commit --auto", "push"];
+
+/// `ScheduleAction` is an enum of the things that can be done with the scheduled sync.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScheduleAction {
+    Install(Duration),
+    Remove,
+    Status,
+}
+
+/// It returns the directory the systemd user units are written to, which is
+/// `~/.config/systemd/user` unless overridden
+///
+/// Returns:
+///
+/// A tuple of the directory and whether it was overridden
+fn get_unit_dir() -> (PathBuf, bool) {
+    match env::var(UNIT_DIR_VAR) {
+        Ok(dir) => (PathBuf::from(dir), true),
+        Err(_) => {
+            let config_home = env::var("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| {
+                    PathBuf::from(env::var("HOME").expect("Failed to find home directory"))
+                        .join(".config")
+                });
+            (config_home.join("systemd").join("user"), false)
+        }
+    }
+}
+
+/// It renders the service unit, a oneshot that runs the sync actions in the working directory with
+/// stdin detached
+///
+/// Returns:
+///
+/// A String
+fn render_service() -> String {
+    let exe = env::current_exe().expect("Failed to find the configman executable");
+    let exec_lines = SYNC_ACTIONS
+        .iter()
+        .map(|action| {
+            format!(
+                "ExecStart={} {}",
+                quote_exec_arg(&exe.display().to_string()),
+                action
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    format!(
+        "[Unit]\nDescription=Sync dotfiles with configman\n\n[Service]\nType=oneshot\nStandardInput=null\nWorkingDirectory={}\n{}\n",
+        get_working_dir().display().to_string().replace('%', "%%"),
+        exec_lines
+    )
+}
+
+/// It quotes an argument of an `ExecStart` line the way systemd parses it, so that spaces, quotes,
+/// specifiers and variables are taken literally
+///
+/// Arguments:
+///
+/// * `arg`: The argument.
+///
+/// Returns:
+///
+/// A String
+fn quote_exec_arg(arg: &str) -> String {
+    format!(
+        "\"{}\"",
+        arg.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('%', "%%")
+            .replace('$', "$$")
+    )
+}
+
+/// It renders the timer unit, which runs the service at the given interval
+///
+/// Arguments:
+///
+/// * `every`: The interval between syncs.
+///
+/// Returns:
+///
+/// A String
+fn render_timer(every: &Duration) -> String {
+    format!(
+        "[Unit]\nDescription=Sync dotfiles with configman every {secs}s\n\n[Timer]\nOnBootSec={secs}s\nOnUnitActiveSec={secs}s\nUnit={UNIT_NAME}.service\n\n[Install]\nWantedBy=timers.target\n",
+        secs = every.as_secs()
+    )
+}
+
+/// It runs `systemctl --user` with the given arguments, reporting rather than failing on errors
+///
+/// Arguments:
+///
+/// * `arg`: Vec<&str> - The arguments following `systemctl --user`.
+fn exec_systemctl(arg: Vec<&str>) {
+    match Command::new("systemctl").arg("--user").args(&arg).status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => info!("systemctl --user {} exited with {}", arg.join(" "), status),
+        Err(e) => error!("Failed to run systemctl --user {}: {:?}", arg.join(" "), e),
+    }
+}
+
+/// It writes the service and timer units and, unless the unit directory is overridden, enables the
+/// timer
+///
+/// Arguments:
+///
+/// * `every`: The interval between syncs.
+pub fn install(every: &Duration) {
+    if every.is_zero() {
+        panic!("The sync interval must be longer than zero seconds");
+    }
+    let (dir, overridden) = get_unit_dir();
+    write_units(&dir, every);
+    if !overridden {
+        exec_systemctl(vec!["daemon-reload"]);
+        exec_systemctl(vec!["enable", "--now", &format!("{UNIT_NAME}.timer")]);
+    }
+}
+
+/// It disables the timer, unless the unit directory is overridden, and removes both units
+pub fn remove() {
+    let (dir, overridden) = get_unit_dir();
+    if !overridden {
+        exec_systemctl(vec!["disable", "--now", &format!("{UNIT_NAME}.timer")]);
+    }
+    remove_units(&dir);
+    if !overridden {
+        exec_systemctl(vec!["daemon-reload"]);
+    }
+}
+
+/// It writes the service and timer units to a directory, creating it if needed
+///
+/// Arguments:
+///
+/// * `dir`: The directory of the systemd user units.
+/// * `every`: The interval between syncs.
+fn write_units(dir: &Path, every: &Duration) {
+    fs::create_dir_all(dir).unwrap_or_else(|_| panic!("Failed to create {:?}", &dir));
+    for (extension, content) in [
+        ("service", render_service()),
+        ("timer", render_timer(every)),
+    ] {
+        let path = dir.join(format!("{UNIT_NAME}.{extension}"));
+        fs::write(&path, content).unwrap_or_else(|_| panic!("Failed to write {:?}", &path));
+        info!("Wrote {:?}", &path);
+    }
+}
+
+/// It removes the service and timer units from a directory, if they are there
+///
+/// Arguments:
+///
+/// * `dir`: The directory of the systemd user units.
+fn remove_units(dir: &Path) {
+    for extension in ["service", "timer"] {
+        let path = dir.join(format!("{UNIT_NAME}.{extension}"));
+        if path.exists() {
+            remove_from_fs(&path);
+            info!("Removed {:?}", &path);
+        }
+    }
+}
+
+/// It reports whether the units are installed, the interval and working directory they sync, and,
+/// unless the unit directory is overridden, the state of the timer
+pub fn status() {
+    let (dir, overridden) = get_unit_dir();
+    let timer = fs::read_to_string(dir.join(format!("{UNIT_NAME}.timer"))).ok();
+    let service = fs::read_to_string(dir.join(format!("{UNIT_NAME}.service"))).ok();
+    match (&timer, &service) {
+        (Some(timer), Some(service)) => {
+            let value = |content: &str, key: &str| {
+                content
+                    .lines()
+                    .find_map(|l| l.strip_prefix(key))
+                    .unwrap_or("unknown")
+                    .to_string()
+            };
+            println!("Scheduled sync is installed in {:?}", &dir);
+            println!("  every: {}", value(timer, "OnUnitActiveSec="));
+            println!(
+                "  working directory: {}",
+                value(service, "WorkingDirectory=").replace("%%", "%")
+            );
+        }
+        _ => println!("Scheduled sync is not installed in {:?}", &dir),
+    }
+    if !overridden && timer.is_some() {
+        exec_systemctl(vec![
+            "list-timers",
+            "--all",
+            "--no-pager",
+            &format!("{UNIT_NAME}.timer"),
+        ]);
+    }
+}
+
+/// It performs a schedule action
+///
+/// Arguments:
+///
+/// * `action`: The schedule action.
+pub fn schedule(action: &ScheduleAction) {
+    match action {
+        ScheduleAction::Install(every) => install(every),
+        ScheduleAction::Remove => remove(),
+        ScheduleAction::Status => status(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quote_exec_arg_escapes_what_systemd_would_expand() {
+        assert_eq!(
+            quote_exec_arg("/opt/my apps/configman"),
+            "\"/opt/my apps/configman\""
+        );
+        assert_eq!(quote_exec_arg("/a\\b\"c%d$e"), "\"/a\\\\b\\\"c%%d$$e\"");
+    }
+
+    #[test]
+    fn write_and_remove_units_in_the_given_dir() {
+        let dir = env::temp_dir().join(format!("configman-systemd-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        write_units(&dir, &Duration::from_secs(3600));
+
+        let service = fs::read_to_string(dir.join(format!("{UNIT_NAME}.service")))
+            .expect("Failed to read the service unit");
+        let exe = quote_exec_arg(&env::current_exe().unwrap().display().to_string());
+        let wdir = get_working_dir().display().to_string().replace('%', "%%");
+        assert_eq!(
+            service,
+            format!(
+                "[Unit]\nDescription=Sync dotfiles with configman\n\n[Service]\nType=oneshot\nStandardInput=null\nWorkingDirectory={wdir}\nExecStart={exe} refresh\nExecStart={exe} commit --auto\nExecStart={exe} push\n"
+            )
+        );
+        let timer = fs::read_to_string(dir.join(format!("{UNIT_NAME}.timer")))
+            .expect("Failed to read the timer unit");
+        assert_eq!(
+            timer,
+            "[Unit]\nDescription=Sync dotfiles with configman every 3600s\n\n[Timer]\nOnBootSec=3600s\nOnUnitActiveSec=3600s\nUnit=configman-sync.service\n\n[Install]\nWantedBy=timers.target\n"
+        );
+
+        remove_units(&dir);
+        assert!(!dir.join(format!("{UNIT_NAME}.service")).exists());
+        assert!(!dir.join(format!("{UNIT_NAME}.timer")).exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,585 @@
+use log::{info, warn};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use toml_edit::{value, Array, ArrayOfTables, DocumentMut, Item, Table, Value};
+
+use crate::config::{
+    considered_mapping, convert_line_to_mapping, convert_line_to_setting, load_config, Config,
+    Mapping, Setting, CONFIG_FILE, TOML_CONFIG_FILE,
+};
+use crate::fs::get_working_dir;
+use crate::git::{add_file, rm_cached, GitError};
+
+/// The key of the array of tables declaring the mappings, i.e. `[[mapping]]`.
+pub const MAPPINGS_KEY: &str = "mapping";
+
+/// It parses a structured config file. Top level keys are global settings and every `[[mapping]]`
+/// table is a mapping with a `source`, a `destination` and its own settings. Settings are strings,
+/// booleans or integers, and arrays of them declare a setting once per element.
+///
+/// Arguments:
+///
+/// * `content`: The content of the config file.
+///
+/// Returns:
+///
+/// A Result<Config, Error>
+pub fn parse_toml_config(content: &str) -> Result<Config, Error> {
+    let document = content
+        .parse::<DocumentMut>()
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    let mut config = Config::default();
+    for (key, item) in document.iter().filter(|(key, _)| *key != MAPPINGS_KEY) {
+        config.settings.extend(convert_item_to_settings(key, item)?);
+    }
+    if let Some(item) = document.get(MAPPINGS_KEY) {
+        let tables = item.as_array_of_tables().ok_or_else(|| {
+            invalid(format!(
+                "Expected {MAPPINGS_KEY} to be [[{MAPPINGS_KEY}]] tables"
+            ))
+        })?;
+        for table in tables {
+            config.mappings.push(convert_table_to_mapping(table)?);
+        }
+    }
+    Ok(config)
+}
+
+/// It reads and parses a structured config file
+///
+/// Arguments:
+///
+/// * `path`: &str - The path to the config file
+///
+/// Returns:
+///
+/// A Result<Config, Error>
+pub fn load_toml_config(path: &str) -> Result<Config, Error> {
+    parse_toml_config(&fs::read_to_string(path)?)
+}
+
+/// It converts a `[[mapping]]` table to a Mapping
+///
+/// Arguments:
+///
+/// * `table`: The table of the mapping.
+///
+/// Returns:
+///
+/// A Result<Mapping, Error>
+fn convert_table_to_mapping(table: &Table) -> Result<Mapping, Error> {
+    let field = |name: &str| {
+        table
+            .get(name)
+            .and_then(Item::as_str)
+            .map(String::from)
+            .ok_or_else(|| invalid(format!("Expected every mapping to have a {name} string")))
+    };
+    let mut mapping = Mapping {
+        source: field("source")?,
+        destination: field("destination")?,
+        settings: Vec::new(),
+    };
+    for (key, item) in table
+        .iter()
+        .filter(|(key, _)| !["source", "destination"].contains(key))
+    {
+        mapping
+            .settings
+            .extend(convert_item_to_settings(key, item)?);
+    }
+    Ok(mapping)
+}
+
+/// It converts a key and its value to settings, one for every element of an array
+///
+/// Arguments:
+///
+/// * `key`: The name of the setting.
+/// * `item`: The value of the setting.
+///
+/// Returns:
+///
+/// A Result<Vec<Setting>, Error>
+fn convert_item_to_settings(key: &str, item: &Item) -> Result<Vec<Setting>, Error> {
+    let values = match item.as_value() {
+        Some(Value::Array(array)) => array.iter().collect::<Vec<&Value>>(),
+        Some(value) => vec![value],
+        None => return Err(invalid(format!("Expected {key} to be a setting"))),
+    };
+    values
+        .into_iter()
+        .map(|v| match v {
+            Value::String(s) => Ok(s.value().to_string()),
+            Value::Boolean(b) => Ok(b.value().to_string()),
+            Value::Integer(i) => Ok(i.value().to_string()),
+            _ => Err(invalid(format!(
+                "Expected {key} to be a string, a boolean or an integer"
+            ))),
+        })
+        .map(|v| {
+            v.map(|value| Setting {
+                key: key.to_string(),
+                value,
+            })
+        })
+        .collect()
+}
+
+/// It converts the value of a setting to a TOML value, booleans being typed as such
+///
+/// Arguments:
+///
+/// * `setting`: The value of the setting.
+///
+/// Returns:
+///
+/// A Value
+fn convert_setting_to_value(setting: &str) -> Value {
+    match setting {
+        "true" => Value::from(true),
+        "false" => Value::from(false),
+        _ => Value::from(setting),
+    }
+}
+
+/// It converts the values of a setting to a TOML value, an array if it's declared more than once
+///
+/// Arguments:
+///
+/// * `values`: The values of every declaration of the setting.
+///
+/// Returns:
+///
+/// A Value
+fn convert_settings_to_value(values: &[String]) -> Value {
+    match values {
+        [single] => convert_setting_to_value(single),
+        _ => Value::Array(values.iter().map(|v| convert_setting_to_value(v)).collect()),
+    }
+}
+
+/// It reads a structured config file as an editable document, keeping its comments and layout
+///
+/// Arguments:
+///
+/// * `path`: &str - The path to the config file
+///
+/// Returns:
+///
+/// A Result<DocumentMut, Error>
+fn read_document(path: &str) -> Result<DocumentMut, Error> {
+    fs::read_to_string(path)?
+        .parse::<DocumentMut>()
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+/// It sets a global setting in a structured config file, replacing its value if it's declared
+///
+/// Arguments:
+///
+/// * `path`: &str - The path to the config file
+/// * `key`: The name of the setting.
+/// * `setting`: The value of the setting.
+///
+/// Returns:
+///
+/// A Result<(), Error>
+pub fn write_toml_setting(path: &str, key: &str, setting: &str) -> Result<(), Error> {
+    let mut document = read_document(path)?;
+    document[key] = value(convert_setting_to_value(setting));
+    fs::write(path, document.to_string())
+}
+
+/// It adds a global setting to a structured config file, turning the setting into an array if it's
+/// declared already
+///
+/// Arguments:
+///
+/// * `path`: &str - The path to the config file
+/// * `key`: The name of the setting.
+/// * `setting`: The value of the setting.
+///
+/// Returns:
+///
+/// A Result<(), Error>
+pub fn append_toml_setting(path: &str, key: &str, setting: &str) -> Result<(), Error> {
+    let mut document = read_document(path)?;
+    let added = convert_setting_to_value(setting);
+    match document.get_mut(key).and_then(Item::as_value_mut) {
+        Some(Value::Array(array)) => array.push(added),
+        Some(existing) => {
+            let mut array = Array::new();
+            array.push(existing.clone());
+            array.push(added);
+            array.fmt();
+            *existing = Value::Array(array);
+        }
+        None => document[key] = value(added),
+    }
+    fs::write(path, document.to_string())
+}
+
+/// It appends a `[[mapping]]` table to a structured config file
+///
+/// Arguments:
+///
+/// * `path`: &str - The path to the config file
+/// * `mapping`: The mapping to append.
+///
+/// Returns:
+///
+/// A Result<(), Error>
+pub fn append_toml_mapping(path: &str, mapping: &Mapping) -> Result<(), Error> {
+    let mut document = read_document(path)?;
+    let mut table = Table::new();
+    table["source"] = value(&mapping.source);
+    table["destination"] = value(&mapping.destination);
+    for (key, values) in group_settings(&mapping.settings) {
+        table[key.as_str()] = value(convert_settings_to_value(&values));
+    }
+    let tables = document
+        .entry(MAPPINGS_KEY)
+        .or_insert(Item::ArrayOfTables(ArrayOfTables::new()))
+        .as_array_of_tables_mut()
+        .ok_or_else(|| {
+            invalid(format!(
+                "Expected {MAPPINGS_KEY} to be [[{MAPPINGS_KEY}]] tables"
+            ))
+        })?;
+    tables.push(table);
+    fs::write(path, document.to_string())
+}
+
+/// It removes the `[[mapping]]` table of a mapping from a structured config file
+///
+/// Arguments:
+///
+/// * `path`: &str - The path to the config file
+/// * `mapping`: The mapping to remove.
+///
+/// Returns:
+///
+/// A Result of whether the mapping was found
+pub fn remove_toml_mapping(path: &str, mapping: &Mapping) -> Result<bool, Error> {
+    let mut document = read_document(path)?;
+    let Some(tables) = document
+        .get_mut(MAPPINGS_KEY)
+        .and_then(Item::as_array_of_tables_mut)
+    else {
+        return Ok(false);
+    };
+    let position = tables.iter().position(|table| {
+        table.get("source").and_then(Item::as_str) == Some(mapping.source.as_str())
+            && table.get("destination").and_then(Item::as_str) == Some(mapping.destination.as_str())
+    });
+    let Some(index) = position else {
+        return Ok(false);
+    };
+    tables.remove(index);
+    fs::write(path, document.to_string())?;
+    Ok(true)
+}
+
+/// It finds the number of the `[[mapping]]` line of a mapping in a structured config file
+///
+/// Arguments:
+///
+/// * `path`: &str - The path to the config file
+/// * `mapping`: The config mapping.
+///
+/// Returns:
+///
+/// A Result of the line number, counting from 1, if the mapping was found
+pub fn find_toml_mapping_line(path: &str, mapping: &Mapping) -> Result<Option<usize>, Error> {
+    let content = fs::read_to_string(path)?;
+    let config = parse_toml_config(&content)?;
+    let Some(position) = config
+        .mappings
+        .iter()
+        .position(|m| m.source == mapping.source && m.destination == mapping.destination)
+    else {
+        return Ok(None);
+    };
+    Ok(find_table_lines(&content).get(position).copied())
+}
+
+/// It finds the numbers of the `[[mapping]]` lines of a structured config file
+///
+/// Arguments:
+///
+/// * `content`: The content of the config file.
+///
+/// Returns:
+///
+/// A vector of line numbers, counting from 1
+pub fn find_table_lines(content: &str) -> Vec<usize> {
+    let header = format!("[[{MAPPINGS_KEY}]]");
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.trim() == header)
+        .map(|(index, _)| index + 1)
+        .collect()
+}
+
+/// It groups the declarations of settings by name, in the order each name is first declared
+///
+/// Arguments:
+///
+/// * `settings`: The settings.
+///
+/// Returns:
+///
+/// A vector of the names along with the values of every declaration
+fn group_settings(settings: &[Setting]) -> Vec<(String, Vec<String>)> {
+    let mut grouped: Vec<(String, Vec<String>)> = Vec::new();
+    for setting in settings {
+        match grouped.iter_mut().find(|(key, _)| *key == setting.key) {
+            Some((_, values)) => values.push(setting.value.clone()),
+            None => grouped.push((setting.key.clone(), vec![setting.value.clone()])),
+        }
+    }
+    grouped
+}
+
+/// `TomlEntry` is a struct that describes a setting, or a mapping with its settings, of the
+/// structured config file being written, along with the comments preceding it.
+///
+/// Properties:
+///
+/// * `comments`: The comment and blank lines preceding the entry.
+/// * `key`: The name of the setting, or the source and destination of the mapping.
+/// * `values`: The values of every declaration of the setting.
+/// * `settings`: The settings of the mapping.
+#[derive(Debug, Clone, Default)]
+struct TomlEntry {
+    comments: Vec<String>,
+    key: String,
+    values: Vec<String>,
+    settings: Vec<TomlEntry>,
+}
+
+/// It converts the content of a line based config file to a structured one. Comments are kept above
+/// the setting or mapping they precede, global settings are moved to the top, since TOML requires
+/// them before the first table, and settings declared more than once become arrays.
+///
+/// Arguments:
+///
+/// * `content`: The content of the line based config file.
+///
+/// Returns:
+///
+/// A String
+pub fn convert_cmf_to_toml(content: &str) -> String {
+    let mut globals: Vec<TomlEntry> = Vec::new();
+    let mut mappings: Vec<(TomlEntry, Mapping)> = Vec::new();
+    let mut comments: Vec<String> = Vec::new();
+    for line in content.lines() {
+        if !considered_mapping(line) {
+            let comment = line.trim().to_string();
+            if !(comment.is_empty() && comments.last().is_none_or(String::is_empty)) {
+                comments.push(comment);
+            }
+            continue;
+        }
+        let indented = line.starts_with(char::is_whitespace);
+        match convert_line_to_setting(line) {
+            Some(setting) => {
+                let entries = match mappings.last_mut() {
+                    Some((entry, _)) if indented => &mut entry.settings,
+                    _ => &mut globals,
+                };
+                match entries.iter_mut().find(|e| e.key == setting.key) {
+                    Some(entry) => {
+                        entry.comments.append(&mut comments);
+                        entry.values.push(setting.value);
+                    }
+                    None => entries.push(TomlEntry {
+                        comments: std::mem::take(&mut comments),
+                        key: setting.key,
+                        values: vec![setting.value],
+                        settings: Vec::new(),
+                    }),
+                }
+            }
+            None => {
+                let entry = TomlEntry {
+                    comments: std::mem::take(&mut comments),
+                    ..TomlEntry::default()
+                };
+                mappings.push((entry, convert_line_to_mapping(line.to_string())));
+            }
+        }
+    }
+    let mut lines: Vec<String> = Vec::new();
+    for entry in &globals {
+        write_toml_entry(&mut lines, entry);
+    }
+    for (entry, mapping) in &mappings {
+        let mut entry_comments = entry.comments.clone();
+        if !lines.is_empty() && entry_comments.first().is_none_or(|c| !c.is_empty()) {
+            entry_comments.insert(0, String::new());
+        }
+        lines.extend(entry_comments);
+        lines.push(format!("[[{MAPPINGS_KEY}]]"));
+        lines.push(format!("source = {}", Value::from(&mapping.source)));
+        lines.push(format!(
+            "destination = {}",
+            Value::from(&mapping.destination)
+        ));
+        for setting in &entry.settings {
+            write_toml_entry(&mut lines, setting);
+        }
+    }
+    lines.extend(comments);
+    while lines.last().is_some_and(String::is_empty) {
+        lines.pop();
+    }
+    while lines.first().is_some_and(String::is_empty) {
+        lines.remove(0);
+    }
+    lines.join("\n") + "\n"
+}
+
+/// It writes a setting along with the comments preceding it
+///
+/// Arguments:
+///
+/// * `lines`: The lines written so far.
+/// * `entry`: The setting.
+fn write_toml_entry(lines: &mut Vec<String>, entry: &TomlEntry) {
+    lines.extend(entry.comments.iter().cloned());
+    lines.push(format!(
+        "{} = {}",
+        toml_edit::Key::new(entry.key.as_str()),
+        convert_settings_to_value(&entry.values)
+    ));
+}
+
+/// It converts `config.cmf` to `configman.toml`, checks that both declare the same mappings and
+/// settings, then replaces the former with the latter, in the index as well
+///
+/// Returns:
+///
+/// A Result<(), GitError>
+pub fn migrate_config() -> Result<(), GitError> {
+    let wdir = get_working_dir();
+    let (cmf_path, toml_path) = (wdir.join(CONFIG_FILE), wdir.join(TOML_CONFIG_FILE));
+    if toml_path.exists() {
+        warn!("{TOML_CONFIG_FILE} exists already. Remove it first to migrate {CONFIG_FILE} again.");
+        return Ok(());
+    }
+    let content = fs::read_to_string(&cmf_path)
+        .unwrap_or_else(|_| panic!("Failed to read {:?}. Try initializing first.", &cmf_path));
+    let converted = convert_cmf_to_toml(&content);
+    let before = load_config(&cmf_path.display().to_string())
+        .expect("Failed to load config file. Try initializing first.");
+    let after = parse_toml_config(&converted)
+        .unwrap_or_else(|e| panic!("Failed to convert {CONFIG_FILE}: {:?}", e));
+    if !is_same_config(&before, &after) {
+        panic!(
+            "Failed to convert {CONFIG_FILE} without changing its meaning. It was left untouched."
+        );
+    }
+    fs::write(&toml_path, converted).unwrap_or_else(|_| panic!("Failed to write {:?}", &toml_path));
+    rm_cached(&cmf_path.display().to_string())?;
+    fs::remove_file(&cmf_path).unwrap_or_else(|_| panic!("Failed to remove {:?}", &cmf_path));
+    add_file(&[toml_path.display().to_string()])?;
+    info!("Migrated {CONFIG_FILE} to {TOML_CONFIG_FILE}");
+    Ok(())
+}
+
+/// It checks whether two configs declare the same mappings and settings. Only the order of
+/// declarations of different settings may differ.
+///
+/// Arguments:
+///
+/// * `a`: The first config.
+/// * `b`: The second config.
+///
+/// Returns:
+///
+/// A boolean value.
+fn is_same_config(a: &Config, b: &Config) -> bool {
+    let sorted = |settings: &[Setting]| {
+        let mut settings = settings.to_vec();
+        settings.sort_by(|x, y| x.key.cmp(&y.key));
+        settings
+    };
+    sorted(&a.settings) == sorted(&b.settings)
+        && a.mappings.len() == b.mappings.len()
+        && a.mappings.iter().zip(&b.mappings).all(|(x, y)| {
+            x.source == y.source
+                && x.destination == y.destination
+                && sorted(&x.settings) == sorted(&y.settings)
+        })
+}
+
+/// It creates an error for invalid config content
+///
+/// Arguments:
+///
+/// * `message`: The description of the problem.
+///
+/// Returns:
+///
+/// An Error
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CMF: &str = "# dotfiles\n@remote = origin\n\n/etc/hosts : hosts\n    # keep backups out\n    @exclude = *.bak\n    @exclude = *.orig\n    @allow-binary = true\n@branch = main\n\n# shell\n/home/u/.bashrc : bash/bashrc\n# trailing\n";
+
+    fn load_cmf(name: &str, content: &str) -> Config {
+        let path = std::env::temp_dir().join(format!(
+            "configman-toml-{}-{}.cmf",
+            name,
+            std::process::id()
+        ));
+        fs::write(&path, content).expect("Failed to write the test config");
+        let config = load_config(&path.display().to_string()).expect("Failed to load config");
+        let _ = fs::remove_file(&path);
+        config
+    }
+
+    #[test]
+    fn convert_cmf_to_toml_keeps_comments_and_moves_globals_up() {
+        assert_eq!(
+            convert_cmf_to_toml(CMF),
+            "# dotfiles\nremote = \"origin\"\nbranch = \"main\"\n\n[[mapping]]\nsource = \"/etc/hosts\"\ndestination = \"hosts\"\n# keep backups out\nexclude = [\"*.bak\", \"*.orig\"]\nallow-binary = true\n\n# shell\n[[mapping]]\nsource = \"/home/u/.bashrc\"\ndestination = \"bash/bashrc\"\n# trailing\n"
+        );
+    }
+
+    #[test]
+    fn convert_cmf_to_toml_round_trips() {
+        let before = load_cmf("round-trip", CMF);
+        let after = parse_toml_config(&convert_cmf_to_toml(CMF)).expect("Failed to parse TOML");
+        assert!(is_same_config(&before, &after));
+        assert_eq!(after.mappings.len(), 2);
+        assert_eq!(
+            after.mappings[0].settings_named("exclude"),
+            vec!["*.bak", "*.orig"]
+        );
+    }
+
+    #[test]
+    fn convert_cmf_to_toml_quotes_special_characters() {
+        let cmf = "/home/u/My \"Notes\"/(*.md) : notes/(1)\n";
+        let before = load_cmf("quotes", cmf);
+        let after = parse_toml_config(&convert_cmf_to_toml(cmf)).expect("Failed to parse TOML");
+        assert!(is_same_config(&before, &after));
+    }
+
+    #[test]
+    fn is_same_config_notices_changed_mappings() {
+        let before = load_cmf("changed", CMF);
+        let after = parse_toml_config(&convert_cmf_to_toml(&CMF.replace("bash/bashrc", "bashrc")))
+            .expect("Failed to parse TOML");
+        assert!(!is_same_config(&before, &after));
+    }
+}
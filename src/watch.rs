@@ -0,0 +1,201 @@
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use log::{error, info, warn};
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::config::{get_config_path, load_config, Config, CONFIG_FILE, TOML_CONFIG_FILE};
+use crate::fs::{get_pattern_root, get_working_dir};
+use crate::menu::{run_once, CommitOptions, State};
+use crate::process::{get_found_mappings, RefreshOptions};
+
+/// The events that indicate a source file was written, replaced, added or removed. Attribute changes
+/// are left out since linking a file into the working directory changes its link count.
+const WATCH_MASK: WatchMask = WatchMask::CLOSE_WRITE
+    .union(WatchMask::CREATE)
+    .union(WatchMask::DELETE)
+    .union(WatchMask::MOVED_FROM)
+    .union(WatchMask::MOVED_TO);
+
+/// How long to sleep between checks for new events.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// `WatchOptions` is a struct that describes how the watch loop reacts to changes.
+///
+/// Properties:
+///
+/// * `debounce`: How long no events must arrive before the working directory is refreshed.
+/// * `commit_after`: How long after a refresh nothing must change before committing, if at all.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchOptions {
+    pub debounce: Duration,
+    pub commit_after: Option<Duration>,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        WatchOptions {
+            debounce: Duration::from_secs(2),
+            commit_after: None,
+        }
+    }
+}
+
+/// It watches the directories of all source files, the roots of all source patterns and the config
+/// file, refreshes the working directory once the events have settled and, if asked to, commits the
+/// changes with a generated message after a quiet period. Refreshing runs its hooks and is journaled
+/// like the `refresh` action, and is skipped when the files were only written in place, since their
+/// copies are hard links that are up to date already. It only returns if watching fails.
+///
+/// Arguments:
+///
+/// * `options`: The watch options.
+pub fn watch(options: &WatchOptions) {
+    let mut inotify = Inotify::init().expect("Failed to initialize inotify");
+    let wdir = get_working_dir();
+    let config_wd = inotify
+        .watches()
+        .add(&wdir, WATCH_MASK)
+        .expect("Failed to watch working directory");
+    let mut config = load_watched_config();
+    let mut watched = HashMap::new();
+    add_watches(&mut inotify, &mut watched, &config);
+    info!(
+        "Watching {} directories for changes. Press Ctrl+C to stop.",
+        watched.len()
+    );
+
+    let mut buffer = [0; 4096];
+    let mut last_event: Option<Instant> = None;
+    let mut last_refresh: Option<Instant> = None;
+    let mut needs_refresh = false;
+    loop {
+        match inotify.read_events(&mut buffer) {
+            Ok(events) => {
+                for event in events {
+                    if event.wd == config_wd
+                        && !event
+                            .name
+                            .is_some_and(|n| n == CONFIG_FILE || n == TOML_CONFIG_FILE)
+                    {
+                        continue;
+                    }
+                    let path = watched
+                        .get(&event.wd)
+                        .zip(event.name)
+                        .map(|(dir, name)| Path::new(dir).join(name));
+                    if event.mask.contains(EventMask::CREATE | EventMask::ISDIR) {
+                        if let Some(created) = path.clone() {
+                            add_watch(&mut inotify, &mut watched, created);
+                        }
+                    }
+                    needs_refresh |= event.wd == config_wd
+                        || !event.mask.contains(EventMask::CLOSE_WRITE)
+                        || !path.is_some_and(|p| is_hard_linked(&p));
+                    last_event = Some(Instant::now());
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => panic!("Failed to read file system events: {:?}", e),
+        }
+        if last_event.is_some_and(|t| t.elapsed() >= options.debounce) {
+            last_event = None;
+            if needs_refresh {
+                needs_refresh = false;
+                if let Err(e) = run_once(State::Refresh(RefreshOptions::default())) {
+                    error!("{e}");
+                }
+                config = load_watched_config();
+                add_watches(&mut inotify, &mut watched, &config);
+            }
+            last_refresh = Some(Instant::now());
+        }
+        if let Some(quiet_period) = options.commit_after {
+            if last_event.is_none() && last_refresh.is_some_and(|t| t.elapsed() >= quiet_period) {
+                last_refresh = None;
+                auto_commit();
+            }
+        }
+        sleep(POLL_INTERVAL);
+    }
+}
+
+/// It loads the config file of the working directory
+///
+/// Returns:
+///
+/// A Config
+fn load_watched_config() -> Config {
+    load_config(&get_config_path()).expect("Failed to load config file. Try initializing first.")
+}
+
+/// It checks whether a file has other hard links, which a source file linked into the working
+/// directory has
+///
+/// Arguments:
+///
+/// * `path`: The path of the file.
+///
+/// Returns:
+///
+/// A boolean value.
+fn is_hard_linked(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|m| m.is_file() && m.nlink() > 1)
+}
+
+/// It watches the root of every source pattern and the directory of every found source file that
+/// isn't watched yet
+///
+/// Arguments:
+///
+/// * `inotify`: The inotify instance.
+/// * `watched`: The watched directories by their watch descriptors.
+/// * `config`: &Config
+fn add_watches(
+    inotify: &mut Inotify,
+    watched: &mut HashMap<WatchDescriptor, PathBuf>,
+    config: &Config,
+) {
+    let roots = config.mappings.iter().map(|m| get_pattern_root(&m.source));
+    let parents = get_found_mappings(config)
+        .into_iter()
+        .filter_map(|m| Path::new(&m.source).parent().map(Path::to_path_buf));
+    for dir in roots.chain(parents) {
+        add_watch(inotify, watched, dir);
+    }
+}
+
+/// It watches a directory unless it is already watched or doesn't exist
+///
+/// Arguments:
+///
+/// * `inotify`: The inotify instance.
+/// * `watched`: The watched directories by their watch descriptors.
+/// * `dir`: The directory to watch.
+fn add_watch(inotify: &mut Inotify, watched: &mut HashMap<WatchDescriptor, PathBuf>, dir: PathBuf) {
+    if dir.is_dir() && !watched.values().any(|d| d == &dir) {
+        match inotify.watches().add(&dir, WATCH_MASK) {
+            Ok(wd) => {
+                watched.insert(wd, dir);
+            }
+            Err(e) => warn!("Failed to watch {:?}: {:?}", &dir, e),
+        }
+    }
+}
+
+/// It commits every changed file with a message generated from the changes, running the hooks like the
+/// `commit --auto` action. A failed commit is reported without stopping the watch, so it's retried on
+/// the next change
+fn auto_commit() {
+    let options = CommitOptions {
+        auto: true,
+        ..CommitOptions::default()
+    };
+    if let Err(e) = run_once(State::Commit(options)) {
+        error!("{e}");
+    }
+}